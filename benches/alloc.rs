@@ -0,0 +1,57 @@
+//! Measures page allocation throughput of `SimpleBm` with many threads allocating and freeing at once.
+//! The allocation path `SimpleBm` had before its free list was sharded is measured for comparison:
+//! a single mutex protected free list, with the page lock taken on allocation and released on free.
+
+use olc_utils::{BufferManager, ExclusiveGuard, SeqLock, SimpleBm};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const OPS_PER_THREAD: usize = 200_000;
+const PAGES_PER_THREAD: usize = 16;
+
+fn run(threads: usize, f: impl Fn() + Sync) -> Duration {
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(&f);
+        }
+    });
+    start.elapsed()
+}
+
+fn simple_bm(threads: usize) -> Duration {
+    let bm = SimpleBm::<[u64; 8]>::new(threads * PAGES_PER_THREAD);
+    let bm = &bm;
+    run(threads, || {
+        for _ in 0..OPS_PER_THREAD {
+            bm.alloc().dealloc();
+        }
+    })
+}
+
+fn single_mutex(threads: usize) -> Duration {
+    let free_list = Mutex::new((0..threads * PAGES_PER_THREAD).collect::<Vec<_>>());
+    let locks: Vec<SeqLock> = (0..threads * PAGES_PER_THREAD).map(|_| SeqLock::new()).collect();
+    run(threads, || {
+        for _ in 0..OPS_PER_THREAD {
+            let pid = free_list.lock().unwrap().pop().unwrap();
+            locks[pid].force_lock_exclusive();
+            locks[std::hint::black_box(pid)].unlock_exclusive();
+            free_list.lock().unwrap().push(pid);
+        }
+    })
+}
+
+fn main() {
+    let max_threads = std::thread::available_parallelism().map_or(1, |n| n.get()).max(8);
+    let mut threads = 1;
+    while threads <= max_threads {
+        let report = |name: &str, time: Duration| {
+            let ops = (threads * OPS_PER_THREAD) as f64 / time.as_secs_f64();
+            println!("{name:>12} {threads:>3} threads: {:>8.2} Mop/s", ops / 1e6);
+        };
+        report("SimpleBm", simple_bm(threads));
+        report("single mutex", single_mutex(threads));
+        threads *= 2;
+    }
+}
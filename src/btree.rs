@@ -0,0 +1,350 @@
+use crate::node::{write, NodeRead};
+use crate::{
+    AllocError, BufferManageGuardUpgrade, BufferManager, BufferManagerExt, BufferManagerGuard, ExclusiveGuard, OPtr,
+    OptimisticGuard, PageId,
+};
+use bytemuck::{bytes_of, bytes_of_mut, Pod};
+use std::marker::PhantomData;
+
+const COUNT_OFFSET: usize = 0;
+const IS_LEAF_OFFSET: usize = 2;
+/// inner nodes only, the child for keys greater than all keys in the node
+const UPPER_OFFSET: usize = 8;
+const HEADER_SIZE: usize = 16;
+/// offset of the root page id in the meta page
+const ROOT_OFFSET: usize = 0;
+
+/// A B+-tree with fixed size keys and values, synchronized using optimistic lock coupling.
+///
+/// Nodes are pages of the buffer manager, and the root page id is kept in a separate meta page,
+/// so the tree can be reopened from a persistent buffer manager using [`BTree::open`].
+/// Inner nodes hold separator keys, child `i` holds the keys less than or equal to separator `i`.
+/// Nodes are split eagerly on the way down, so a split never has to propagate upwards.
+/// Deleting entries does not merge nodes.
+pub struct BTree<'bm, BM: BufferManager<'bm>, K, V> {
+    bm: BM,
+    meta: PageId,
+    _p: PhantomData<&'bm (K, V)>,
+}
+
+impl<'bm, BM, K, V> BTree<'bm, BM, K, V>
+where
+    BM: BufferManager<'bm>,
+    BM::Page: Pod,
+    K: Pod + Ord,
+    V: Pod,
+{
+    /// Allocates a meta page and an empty root.
+    pub fn new(bm: BM) -> Result<Self, AllocError> {
+        assert!(Self::leaf_capacity() >= 2, "page too small for two entries");
+        assert!(Self::inner_capacity() >= 3, "page too small for three separators");
+        assert!(Self::leaf_capacity().max(Self::inner_capacity()) <= u16::MAX as usize, "too many entries per page");
+        let mut root = bm.try_alloc()?;
+        init_node(bytes_of_mut(&mut *root), true);
+        let mut meta = match bm.try_alloc() {
+            Ok(meta) => meta,
+            Err(e) => {
+                root.dealloc();
+                return Err(e);
+            }
+        };
+        write(bytes_of_mut(&mut *meta), ROOT_OFFSET, &root.page_id().x);
+        Ok(BTree { bm, meta: meta.page_id(), _p: PhantomData })
+    }
+
+    /// Opens a tree previously created with [`BTree::new`].
+    pub fn open(bm: BM, meta: PageId) -> Self {
+        BTree { bm, meta, _p: PhantomData }
+    }
+
+    pub fn meta_page_id(&self) -> PageId {
+        self.meta
+    }
+
+    fn page_bytes(guard: &BM::GuardO) -> OPtr<'bm, [u8], BM::OlcEH> {
+        guard.o_ptr_bm().as_slice::<u8>()
+    }
+
+    fn leaf_capacity() -> usize {
+        (size_of::<BM::Page>() - HEADER_SIZE) / (size_of::<K>() + size_of::<V>())
+    }
+
+    fn inner_capacity() -> usize {
+        (size_of::<BM::Page>() - HEADER_SIZE) / (size_of::<K>() + size_of::<u64>())
+    }
+
+    fn count(node: impl NodeRead) -> usize {
+        node.read::<u16>(COUNT_OFFSET) as usize
+    }
+
+    fn is_leaf(node: impl NodeRead) -> bool {
+        node.read::<u16>(IS_LEAF_OFFSET) != 0
+    }
+
+    fn key_offset(i: usize) -> usize {
+        HEADER_SIZE + i * size_of::<K>()
+    }
+
+    fn value_offset(i: usize) -> usize {
+        HEADER_SIZE + Self::leaf_capacity() * size_of::<K>() + i * size_of::<V>()
+    }
+
+    fn child_offset(i: usize) -> usize {
+        HEADER_SIZE + Self::inner_capacity() * size_of::<K>() + i * size_of::<u64>()
+    }
+
+    fn key(node: impl NodeRead, i: usize) -> K {
+        node.read(Self::key_offset(i))
+    }
+
+    fn value(node: impl NodeRead, i: usize) -> V {
+        node.read(Self::value_offset(i))
+    }
+
+    fn child(node: impl NodeRead, i: usize) -> PageId {
+        let offset = if i == Self::count(node) { UPPER_OFFSET } else { Self::child_offset(i) };
+        PageId { x: node.read(offset) }
+    }
+
+    /// index of the first key not less than `key`, or greater than `key` if `exclusive`
+    fn lower_bound(node: impl NodeRead, key: K, exclusive: bool) -> usize {
+        let (mut lower, mut upper) = (0, Self::count(node));
+        while lower < upper {
+            let mid = (lower + upper) / 2;
+            let k = Self::key(node, mid);
+            if k < key || (exclusive && k == key) {
+                lower = mid + 1;
+            } else {
+                upper = mid;
+            }
+        }
+        lower
+    }
+
+    /// Descends to the leaf that holds `key` or the keys following it.
+    /// Also returns the separator bounding the leaf from above, `None` for the rightmost leaf.
+    fn descend(&self, key: K, exclusive: bool) -> (BM::GuardO, Option<K>) {
+        let meta = self.bm.lock_optimistic(self.meta);
+        let root = PageId { x: Self::page_bytes(&meta).read(ROOT_OFFSET) };
+        meta.check();
+        let mut node = self.bm.lock_optimistic(root);
+        // the parent is validated before locking the child so the page id is valid,
+        // and again afterwards so the child was not split in between
+        meta.release();
+        let mut upper = None;
+        loop {
+            let bytes = Self::page_bytes(&node);
+            if Self::is_leaf(bytes) {
+                return (node, upper);
+            }
+            let pos = Self::lower_bound(bytes, key, exclusive);
+            if pos < Self::count(bytes) {
+                upper = Some(Self::key(bytes, pos));
+            }
+            let child = Self::child(bytes, pos);
+            node.check();
+            let next = self.bm.lock_optimistic(child);
+            node.release();
+            node = next;
+        }
+    }
+
+    pub fn lookup(&self, key: K) -> Option<V> {
+        BM::repeat(|| {
+            let (leaf, _) = self.descend(key, false);
+            let bytes = Self::page_bytes(&leaf);
+            let pos = Self::lower_bound(bytes, key, false);
+            let found = pos < Self::count(bytes) && Self::key(bytes, pos) == key;
+            let value = found.then(|| Self::value(bytes, pos));
+            leaf.release();
+            value
+        })
+    }
+
+    /// Calls `f` on the entries with keys not less than `start` in ascending order until it returns `false`.
+    /// Each leaf is copied and validated before `f` sees its entries,
+    /// but entries of different leaves may stem from different points in time.
+    pub fn scan(&self, start: K, mut f: impl FnMut(K, V) -> bool) {
+        let (mut key, mut exclusive) = (start, false);
+        loop {
+            let (entries, upper) = BM::repeat(|| {
+                let (leaf, upper) = self.descend(key, exclusive);
+                let bytes = Self::page_bytes(&leaf);
+                let entries: Vec<(K, V)> = (Self::lower_bound(bytes, key, exclusive)..Self::count(bytes))
+                    .map(|i| (Self::key(bytes, i), Self::value(bytes, i)))
+                    .collect();
+                leaf.release();
+                (entries, upper)
+            });
+            for (k, v) in entries {
+                if !f(k, v) {
+                    return;
+                }
+            }
+            match upper {
+                Some(upper) => (key, exclusive) = (upper, true),
+                None => return,
+            }
+        }
+    }
+
+    /// Inserts or replaces the value for `key`, returns the previous value.
+    /// Fails if a node has to be split and the buffer manager is out of pages, the tree is unchanged then.
+    pub fn insert(&self, key: K, value: V) -> Result<Option<V>, AllocError> {
+        loop {
+            if let Some(previous) = BM::repeat(|| self.try_insert(key, value))? {
+                return Ok(previous);
+            }
+        }
+    }
+
+    /// Returns `None` if a node had to be split first.
+    fn try_insert(&self, key: K, value: V) -> Result<Option<Option<V>>, AllocError> {
+        let meta = self.bm.lock_optimistic(self.meta);
+        let root = PageId { x: Self::page_bytes(&meta).read(ROOT_OFFSET) };
+        meta.check();
+        let mut parent = meta;
+        let mut node = self.bm.lock_optimistic(root);
+        loop {
+            let bytes = Self::page_bytes(&node);
+            if Self::is_leaf(bytes) {
+                if Self::count(bytes) == Self::leaf_capacity() {
+                    self.split(parent, node)?;
+                    return Ok(None);
+                }
+                parent.release();
+                let mut leaf: BM::GuardX = node.upgrade();
+                return Ok(Some(self.insert_into_leaf(bytes_of_mut(&mut *leaf), key, value)));
+            }
+            if Self::count(bytes) == Self::inner_capacity() {
+                self.split(parent, node)?;
+                return Ok(None);
+            }
+            let child = Self::child(bytes, Self::lower_bound(bytes, key, false));
+            node.check();
+            let next = self.bm.lock_optimistic(child);
+            parent.release();
+            parent = node;
+            node = next;
+        }
+    }
+
+    fn insert_into_leaf(&self, leaf: &mut [u8], key: K, value: V) -> Option<V> {
+        let count = Self::count(&*leaf);
+        let pos = Self::lower_bound(&*leaf, key, false);
+        if pos < count && Self::key(&*leaf, pos) == key {
+            let previous = Self::value(&*leaf, pos);
+            write(leaf, Self::value_offset(pos), &value);
+            return Some(previous);
+        }
+        leaf.copy_within(Self::key_offset(pos)..Self::key_offset(count), Self::key_offset(pos + 1));
+        leaf.copy_within(Self::value_offset(pos)..Self::value_offset(count), Self::value_offset(pos + 1));
+        write(leaf, Self::key_offset(pos), &key);
+        write(leaf, Self::value_offset(pos), &value);
+        write(leaf, COUNT_OFFSET, &(count as u16 + 1));
+        None
+    }
+
+    /// Splits `node`, `parent` must have room for another separator or be the meta page.
+    /// All pages are allocated before anything is modified, so nothing changes if that fails.
+    fn split(&self, parent: BM::GuardO, node: BM::GuardO) -> Result<(), AllocError> {
+        let mut parent: BM::GuardX = parent.upgrade();
+        let mut node: BM::GuardX = node.upgrade();
+        let mut right = self.bm.try_alloc()?;
+        let new_root = if parent.page_id() == self.meta {
+            match self.bm.try_alloc() {
+                Ok(root) => Some(root),
+                Err(e) => {
+                    right.dealloc();
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+        let separator = if Self::is_leaf(bytes_of(&*node)) {
+            Self::split_leaf(bytes_of_mut(&mut *node), bytes_of_mut(&mut *right))
+        } else {
+            Self::split_inner(bytes_of_mut(&mut *node), bytes_of_mut(&mut *right))
+        };
+        if let Some(mut root) = new_root {
+            let bytes = bytes_of_mut(&mut *root);
+            init_node(bytes, false);
+            write(bytes, COUNT_OFFSET, &1u16);
+            write(bytes, Self::key_offset(0), &separator);
+            write(bytes, Self::child_offset(0), &node.page_id().x);
+            write(bytes, UPPER_OFFSET, &right.page_id().x);
+            write(bytes_of_mut(&mut *parent), ROOT_OFFSET, &root.page_id().x);
+        } else {
+            let parent = bytes_of_mut(&mut *parent);
+            let count = Self::count(&*parent);
+            let pos = Self::lower_bound(&*parent, separator, false);
+            parent.copy_within(Self::key_offset(pos)..Self::key_offset(count), Self::key_offset(pos + 1));
+            parent.copy_within(Self::child_offset(pos)..Self::child_offset(count), Self::child_offset(pos + 1));
+            write(parent, Self::key_offset(pos), &separator);
+            write(parent, Self::child_offset(pos), &node.page_id().x);
+            write(parent, COUNT_OFFSET, &(count as u16 + 1));
+            // the slot after the separator pointed to the node before the split
+            let right_offset = if pos == count { UPPER_OFFSET } else { Self::child_offset(pos + 1) };
+            write(parent, right_offset, &right.page_id().x);
+        }
+        Ok(())
+    }
+
+    /// Moves the upper half of `left` to `right` and returns the largest key remaining in `left`.
+    fn split_leaf(left: &mut [u8], right: &mut [u8]) -> K {
+        let count = Self::count(&*left);
+        let mid = count / 2;
+        init_node(right, true);
+        right[Self::key_offset(0)..Self::key_offset(count - mid)]
+            .copy_from_slice(&left[Self::key_offset(mid)..Self::key_offset(count)]);
+        right[Self::value_offset(0)..Self::value_offset(count - mid)]
+            .copy_from_slice(&left[Self::value_offset(mid)..Self::value_offset(count)]);
+        write(right, COUNT_OFFSET, &((count - mid) as u16));
+        write(left, COUNT_OFFSET, &(mid as u16));
+        Self::key(&*left, mid - 1)
+    }
+
+    /// Moves the separators after the middle one to `right` and returns the middle one.
+    fn split_inner(left: &mut [u8], right: &mut [u8]) -> K {
+        let count = Self::count(&*left);
+        let mid = count / 2;
+        let separator = Self::key(&*left, mid);
+        init_node(right, false);
+        right[Self::key_offset(0)..Self::key_offset(count - mid - 1)]
+            .copy_from_slice(&left[Self::key_offset(mid + 1)..Self::key_offset(count)]);
+        right[Self::child_offset(0)..Self::child_offset(count - mid - 1)]
+            .copy_from_slice(&left[Self::child_offset(mid + 1)..Self::child_offset(count)]);
+        right[UPPER_OFFSET..UPPER_OFFSET + 8].copy_from_slice(&left[UPPER_OFFSET..UPPER_OFFSET + 8]);
+        write(right, COUNT_OFFSET, &((count - mid - 1) as u16));
+        left.copy_within(Self::child_offset(mid)..Self::child_offset(mid + 1), UPPER_OFFSET);
+        write(left, COUNT_OFFSET, &(mid as u16));
+        separator
+    }
+
+    /// Removes `key`, returns its value.
+    pub fn remove(&self, key: K) -> Option<V> {
+        BM::repeat(|| {
+            let (leaf, _) = self.descend(key, false);
+            let bytes = Self::page_bytes(&leaf);
+            let pos = Self::lower_bound(bytes, key, false);
+            if pos >= Self::count(bytes) || Self::key(bytes, pos) != key {
+                leaf.release();
+                return None;
+            }
+            let mut leaf: BM::GuardX = leaf.upgrade();
+            let leaf = bytes_of_mut(&mut *leaf);
+            let count = Self::count(&*leaf);
+            let value = Self::value(&*leaf, pos);
+            leaf.copy_within(Self::key_offset(pos + 1)..Self::key_offset(count), Self::key_offset(pos));
+            leaf.copy_within(Self::value_offset(pos + 1)..Self::value_offset(count), Self::value_offset(pos));
+            write(leaf, COUNT_OFFSET, &(count as u16 - 1));
+            Some(value)
+        })
+    }
+}
+
+fn init_node(node: &mut [u8], is_leaf: bool) {
+    write(node, COUNT_OFFSET, &0u16);
+    write(node, IS_LEAF_OFFSET, &(is_leaf as u16));
+}
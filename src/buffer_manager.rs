@@ -1,106 +1,328 @@
-use crate::seqlock::SeqLock;
+use crate::dirty_set::DirtySet;
+use crate::free_list::FreeList;
+use crate::seqlock::{LockTimeout, PageLock, SeqLock};
+use crate::wal::{LogRecord, Lsn, Wal};
 use crate::{
-    BufferManageGuardUpgrade, BufferManager, BufferManagerGuard, ExclusiveGuard, OPtr, OlcErrorHandler, OlcVersion,
-    OptimisticGuard, PageId, UnwindOlcEh,
+    BufferManageGuardDowngrade, BufferManageGuardTryUpgrade, BufferManageGuardUpgrade, BufferManager,
+    BufferManagerGuard, ExclusiveGuard, OPtr, OlcErrorHandler, OlcVersion, OptimisticGuard, PageId, UnwindOlcEh,
 };
-use bytemuck::Zeroable;
+use bytemuck::{Pod, Zeroable};
 use std::cell::UnsafeCell;
+use std::fmt::{Display, Formatter};
+use std::io;
 use std::mem::{forget, MaybeUninit};
 use std::ops::{Deref, DerefMut};
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
-pub struct SimpleBm<P> {
+/// Number of segments a growable [`SimpleBm`] may allocate, each twice as large as the one before.
+const MAX_SEGMENTS: usize = 32;
+
+pub struct SimpleBm<P, L: PageLock = SeqLock> {
+    segments: Box<[OnceLock<Segment<P, L>>]>,
+    segment_capacity: usize,
+    free_list: FreeList,
+    grow_lock: Mutex<()>,
+}
+
+struct Segment<P, L: PageLock> {
     pages: Box<[UnsafeCell<P>]>,
-    locks: Box<[SeqLock]>,
-    free_list: Mutex<Vec<usize>>,
+    locks: Box<[L]>,
+    dirty: DirtySet,
+}
+
+/// Returned when a buffer manager cannot allocate a page.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum AllocError {
+    /// Every page is in use.
+    OutOfPages,
+    /// There are free pages, but every buffer frame holds a locked page.
+    PoolExhausted,
+    /// Writing back an evicted page to make room failed.
+    Io(io::ErrorKind),
 }
 
-unsafe impl<P> Sync for SimpleBm<P> {}
+impl Display for AllocError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AllocError::OutOfPages => f.write_str("out of pages"),
+            AllocError::PoolExhausted => f.write_str("no evictable frame, all resident pages are locked"),
+            AllocError::Io(kind) => write!(f, "failed to write back a page: {kind}"),
+        }
+    }
+}
 
-impl<P: Zeroable> SimpleBm<P> {
+unsafe impl<P, L: PageLock> Sync for SimpleBm<P, L> {}
+
+impl<P: Zeroable, L: PageLock> SimpleBm<P, L> {
     pub fn new(capacity: usize) -> Self {
+        Self::with_segments(capacity, 1)
+    }
+
+    /// Creates a buffer manager that starts with `initial_capacity` pages and allocates more when it runs out.
+    /// Pages never move, so references and page ids stay valid across growth.
+    pub fn growable(initial_capacity: usize) -> Self {
+        assert!(initial_capacity > 0);
+        Self::with_segments(initial_capacity, MAX_SEGMENTS)
+    }
+
+    fn with_segments(segment_capacity: usize, max_segments: usize) -> Self {
+        // page ids of later segments would not fit in usize
+        let fits = |n: usize| 1usize.checked_shl(n as u32).and_then(|x| segment_capacity.checked_mul(x - 1)).is_some();
+        let max_segments = (1..=max_segments).take_while(|&n| fits(n)).count();
+        assert!(max_segments > 0, "capacity too large");
+        let bm = SimpleBm {
+            segments: (0..max_segments).map(|_| OnceLock::new()).collect(),
+            segment_capacity,
+            free_list: FreeList::new(),
+            grow_lock: Mutex::new(()),
+        };
+        bm.segments[0].set(Segment::new(segment_capacity)).ok().unwrap();
+        bm.free_list.extend(0..segment_capacity);
+        bm
+    }
+}
+
+impl<P: Zeroable, L: PageLock> Segment<P, L> {
+    fn new(capacity: usize) -> Self {
         unsafe {
-            SimpleBm {
+            Segment {
                 pages: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(capacity)),
                 locks: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(capacity)),
-                free_list: Mutex::new((0..capacity).collect()),
+                dirty: DirtySet::new(capacity),
             }
         }
     }
 }
 
-impl<'bm, P> CommonSeqLockBM<'bm> for &'bm SimpleBm<P> {
+impl<P, L: PageLock> SimpleBm<P, L> {
+    /// first page id in segment `index`
+    fn segment_start(&self, index: usize) -> usize {
+        self.segment_capacity * ((1 << index) - 1)
+    }
+
+    fn locate(&self, pid: usize) -> (&Segment<P, L>, usize) {
+        let index = (pid / self.segment_capacity).checked_add(1).map_or(usize::BITS, usize::ilog2) as usize;
+        let segment = self.segments.get(index).and_then(OnceLock::get).expect("page id out of range");
+        (segment, pid - self.segment_start(index))
+    }
+
+    fn segment_lock(&self, pid: usize) -> &L {
+        let (segment, offset) = self.locate(pid);
+        &segment.locks[offset]
+    }
+
+    /// Returns the pages written through an exclusive guard since their dirty bit was last cleared.
+    pub fn dirty_pages(&self) -> Vec<PageId> {
+        self.collect_dirty(false)
+    }
+
+    /// Like [`dirty_pages`](Self::dirty_pages), but also clears the dirty bits,
+    /// so the next call only returns pages written in between.
+    /// The pages are not locked, a page whose guard is released during the call may also be returned by the next one.
+    pub fn take_dirty_pages(&self) -> Vec<PageId> {
+        self.collect_dirty(true)
+    }
+
+    /// Clears the dirty bit of `pid`, returns whether it was set.
+    pub fn clear_dirty(&self, pid: PageId) -> bool {
+        let (segment, offset) = self.locate(pid.x as usize);
+        segment.dirty.remove(offset)
+    }
+
+    fn collect_dirty(&self, clear: bool) -> Vec<PageId> {
+        let mut dirty = Vec::new();
+        for (index, segment) in self.segments.iter().map_while(|s| s.get()).enumerate() {
+            segment.dirty.collect(clear, self.segment_start(index), &mut dirty);
+        }
+        dirty
+    }
+}
+
+impl<'bm, P: Zeroable, L: PageLock> CommonSeqLockBM<'bm> for &'bm SimpleBm<P, L> {
     type Page = P;
     type OlcEH = UnwindOlcEh;
+    type Lock = L;
 
     fn pid_from_address(self, address: usize) -> PageId {
-        let start = self.pages.as_ptr().addr();
-        debug_assert!(address >= start);
-        debug_assert!(address < start + size_of::<P>() * self.pages.len());
-        let offset = address - start;
-        assert_eq!(offset % size_of::<P>(), 0);
-        PageId { x: (offset / size_of::<P>()) as u64 }
-    }
-
-    fn alloc(self) -> PageId {
-        let pid = self.free_list.lock().unwrap().pop().expect("out of pages");
-        self.locks[pid].force_lock_exclusive();
-        PageId { x: pid as u64 }
+        for (index, segment) in self.segments.iter().map_while(|s| s.get()).enumerate() {
+            let start = segment.pages.as_ptr().addr();
+            if address < start || address >= start + size_of::<P>() * segment.pages.len() {
+                continue;
+            }
+            let offset = address - start;
+            assert_eq!(offset % size_of::<P>(), 0);
+            return PageId { x: (self.segment_start(index) + offset / size_of::<P>()) as u64 };
+        }
+        panic!("address does not belong to buffer manager");
+    }
+
+    fn try_alloc(self) -> Result<PageId, AllocError> {
+        let pid = match self.free_list.pop() {
+            Some(pid) => pid,
+            None => {
+                let _grow_guard = self.grow_lock.lock().unwrap();
+                // another thread may have grown the pool while we were waiting
+                match self.free_list.pop() {
+                    Some(pid) => pid,
+                    None => {
+                        let next = self.segments.iter().position(|s| s.get().is_none()).ok_or(AllocError::OutOfPages)?;
+                        let start = self.segment_start(next);
+                        let len = self.segment_capacity << next;
+                        self.segments[next].set(Segment::new(len)).ok().unwrap();
+                        self.free_list.extend(start + 1..start + len);
+                        start
+                    }
+                }
+            }
+        };
+        self.segment_lock(pid).force_lock_exclusive();
+        Ok(PageId { x: pid as u64 })
     }
 
     fn dealloc(self, pid: PageId) {
-        let pid = pid.x as usize;
-        self.locks[pid].unlock_exclusive();
-        self.free_list.lock().unwrap().push(pid)
+        let (segment, offset) = self.locate(pid.x as usize);
+        segment.dirty.remove(offset);
+        segment.locks[offset].unlock_exclusive();
+        self.free_list.push(pid.x as usize)
     }
 
     fn page(self, pid: PageId) -> &'bm UnsafeCell<Self::Page> {
-        &self.pages[pid.x as usize]
+        let (segment, offset) = self.locate(pid.x as usize);
+        &segment.pages[offset]
+    }
+
+    fn lock(self, pid: PageId) -> &'bm L {
+        self.segment_lock(pid.x as usize)
     }
 
-    fn lock(self, pid: PageId) -> &'bm SeqLock {
-        &self.locks[pid.x as usize]
+    fn mark_dirty(self, pid: PageId) {
+        let (segment, offset) = self.locate(pid.x as usize);
+        segment.dirty.insert(offset);
     }
 }
 
 pub trait CommonSeqLockBM<'bm>: Copy + Sync + Send + 'bm {
     type Page;
     type OlcEH: OlcErrorHandler;
+    type Lock: PageLock;
+    /// The page that `page` currently returns at `address`.
+    /// If pages are evicted, this is only stable while the page is locked.
     fn pid_from_address(self, address: usize) -> PageId;
     /// acquires exclusive lock
-    fn alloc(self) -> PageId;
+    fn try_alloc(self) -> Result<PageId, AllocError>;
+    /// acquires exclusive lock
+    fn alloc(self) -> PageId {
+        match self.try_alloc() {
+            Ok(pid) => pid,
+            Err(e) => panic!("{e}"),
+        }
+    }
     /// releases exclusive lock
     fn dealloc(self, pid: PageId);
     fn page(self, pid: PageId) -> &'bm UnsafeCell<Self::Page>;
-    fn lock(self, pid: PageId) -> &'bm SeqLock;
+    fn lock(self, pid: PageId) -> &'bm Self::Lock;
+    /// Called before the lock of `pid` is acquired.
+    /// Buffer managers that evict pages load the page here.
+    /// Returns false if the page could not be loaded before `deadline`, loading is always tried at least once.
+    /// If every buffer frame holds a locked page, this waits for one to be unlocked, or returns false at `deadline`.
+    /// Panics if the page cannot be read from or written back to a file, guards have no way to report it.
+    fn fix(self, _pid: PageId, _deadline: Option<Instant>) -> bool {
+        true
+    }
+    /// Whether `page` currently returns the contents of `pid`.
+    /// May only change while the page is locked exclusively.
+    fn is_resident(self, _pid: PageId) -> bool {
+        true
+    }
+    /// Called when an exclusive guard that wrote to `pid` is released, before the page is unlocked.
+    fn mark_dirty(self, _pid: PageId) {}
+    /// The log that changes made through [`SimpleGuardX::write_logged`] are appended to.
+    fn wal(self) -> Option<&'bm Wal> {
+        None
+    }
+    /// Records that `lsn` is the last logged change to the page `pid`, which is locked exclusively.
+    /// The log must be flushed up to this LSN before the page is written back.
+    fn set_page_lsn(self, _pid: PageId, _lsn: Lsn) {}
 }
 
 pub struct SimpleGuardO<'bm, BM: CommonSeqLockBM<'bm>> {
     bm: BM,
+    pid: PageId,
     ptr: OPtr<'bm, BM::Page, BM::OlcEH>,
     version: OlcVersion,
 }
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> Clone for SimpleGuardO<'bm, BM> {
     fn clone(&self) -> Self {
-        SimpleGuardO { bm: self.bm, ptr: self.ptr, version: self.version }
+        SimpleGuardO { bm: self.bm, pid: self.pid, ptr: self.ptr, version: self.version }
     }
 }
 
 pub struct SimpleGuardS<'bm, BM: CommonSeqLockBM<'bm>> {
     bm: BM,
+    pid: PageId,
     ptr: &'bm BM::Page,
 }
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManagerGuard<'bm, BM> for SimpleGuardS<'bm, BM> {
     fn acquire_wait(bm: BM, page_id: PageId) -> Self {
-        let Ok(_) = bm.lock(page_id).lock_shared(());
-        SimpleGuardS { bm, ptr: unsafe { &*bm.page(page_id).get() } }
+        loop {
+            bm.fix(page_id, None);
+            let Ok(_) = bm.lock(page_id).lock_shared(());
+            if bm.is_resident(page_id) {
+                return SimpleGuardS { bm, pid: page_id, ptr: unsafe { &*bm.page(page_id).get() } };
+            }
+            bm.lock(page_id).unlock_shared();
+        }
     }
 
     fn acquire_wait_version(bm: BM, page_id: PageId, v: OlcVersion) -> Option<Self> {
+        bm.fix(page_id, None);
         bm.lock(page_id).lock_shared(v).ok()?;
-        Some(SimpleGuardS { bm, ptr: unsafe { &*bm.page(page_id).get() } })
+        if !bm.is_resident(page_id) {
+            bm.lock(page_id).unlock_shared();
+            return None;
+        }
+        Some(SimpleGuardS { bm, pid: page_id, ptr: unsafe { &*bm.page(page_id).get() } })
+    }
+
+    fn try_acquire(bm: BM, page_id: PageId) -> Option<Self> {
+        if !bm.fix(page_id, Some(Instant::now())) {
+            return None;
+        }
+        bm.lock(page_id).try_lock_shared(())?;
+        if !bm.is_resident(page_id) {
+            bm.lock(page_id).unlock_shared();
+            return None;
+        }
+        Some(SimpleGuardS { bm, pid: page_id, ptr: unsafe { &*bm.page(page_id).get() } })
+    }
+
+    fn try_acquire_version(bm: BM, page_id: PageId, v: OlcVersion) -> Option<Self> {
+        if !bm.fix(page_id, Some(Instant::now())) {
+            return None;
+        }
+        bm.lock(page_id).try_lock_shared(v)?;
+        if !bm.is_resident(page_id) {
+            bm.lock(page_id).unlock_shared();
+            return None;
+        }
+        Some(SimpleGuardS { bm, pid: page_id, ptr: unsafe { &*bm.page(page_id).get() } })
+    }
+
+    fn acquire_timeout(bm: BM, page_id: PageId, timeout: Duration) -> Result<Self, LockTimeout> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !bm.fix(page_id, Some(deadline)) {
+                return Err(LockTimeout);
+            }
+            bm.lock(page_id).lock_shared_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            if bm.is_resident(page_id) {
+                return Ok(SimpleGuardS { bm, pid: page_id, ptr: unsafe { &*bm.page(page_id).get() } });
+            }
+            bm.lock(page_id).unlock_shared();
+        }
     }
 
     fn release(self) -> OlcVersion {
@@ -110,7 +332,7 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManagerGuard<'bm, BM> for SimpleGuardS
     }
 
     fn page_id(&self) -> PageId {
-        self.bm.pid_from_address((self.ptr as *const BM::Page).addr())
+        self.pid
     }
 
     fn o_ptr(&mut self) -> OPtr<'_, BM::Page, BM::OlcEH> {
@@ -128,29 +350,85 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> Deref for SimpleGuardS<'bm, BM> {
 
 pub struct SimpleGuardX<'bm, BM: CommonSeqLockBM<'bm>> {
     bm: BM,
+    pid: PageId,
     ptr: &'bm mut BM::Page,
     written: bool,
 }
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManagerGuard<'bm, BM> for SimpleGuardX<'bm, BM> {
     fn acquire_wait(bm: BM, page_id: PageId) -> Self {
-        let Ok(_version) = bm.lock(page_id).lock_exclusive(());
-        SimpleGuardX { bm, ptr: unsafe { &mut *bm.page(page_id).get() }, written: false }
+        loop {
+            bm.fix(page_id, None);
+            let Ok(_version) = bm.lock(page_id).lock_exclusive(());
+            if bm.is_resident(page_id) {
+                return SimpleGuardX { bm, pid: page_id, ptr: unsafe { &mut *bm.page(page_id).get() }, written: false };
+            }
+            bm.lock(page_id).unlock_exclusive();
+        }
     }
 
     fn acquire_wait_version(bm: BM, page_id: PageId, version: OlcVersion) -> Option<Self> {
+        bm.fix(page_id, None);
         bm.lock(page_id).lock_exclusive(version).ok()?;
-        Some(SimpleGuardX { bm, ptr: unsafe { &mut *bm.page(page_id).get() }, written: false })
+        if !bm.is_resident(page_id) {
+            bm.lock(page_id).unlock_exclusive();
+            return None;
+        }
+        Some(SimpleGuardX { bm, pid: page_id, ptr: unsafe { &mut *bm.page(page_id).get() }, written: false })
+    }
+
+    fn try_acquire(bm: BM, page_id: PageId) -> Option<Self> {
+        if !bm.fix(page_id, Some(Instant::now())) {
+            return None;
+        }
+        bm.lock(page_id).try_lock_exclusive(())?;
+        if !bm.is_resident(page_id) {
+            bm.lock(page_id).unlock_exclusive();
+            return None;
+        }
+        Some(SimpleGuardX { bm, pid: page_id, ptr: unsafe { &mut *bm.page(page_id).get() }, written: false })
+    }
+
+    fn try_acquire_version(bm: BM, page_id: PageId, version: OlcVersion) -> Option<Self> {
+        if !bm.fix(page_id, Some(Instant::now())) {
+            return None;
+        }
+        bm.lock(page_id).try_lock_exclusive(version)?;
+        if !bm.is_resident(page_id) {
+            bm.lock(page_id).unlock_exclusive();
+            return None;
+        }
+        Some(SimpleGuardX { bm, pid: page_id, ptr: unsafe { &mut *bm.page(page_id).get() }, written: false })
+    }
+
+    fn acquire_timeout(bm: BM, page_id: PageId, timeout: Duration) -> Result<Self, LockTimeout> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !bm.fix(page_id, Some(deadline)) {
+                return Err(LockTimeout);
+            }
+            bm.lock(page_id).lock_exclusive_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            if bm.is_resident(page_id) {
+                return Ok(SimpleGuardX {
+                    bm,
+                    pid: page_id,
+                    ptr: unsafe { &mut *bm.page(page_id).get() },
+                    written: false,
+                });
+            }
+            bm.lock(page_id).unlock_exclusive();
+        }
     }
 
     fn release(self) -> OlcVersion {
+        self.mark_if_written();
         let version = self.bm.lock(self.page_id()).unlock_exclusive();
         forget(self);
         version
     }
 
     fn page_id(&self) -> PageId {
-        self.bm.pid_from_address((self.ptr as *const BM::Page).addr())
+        self.pid
     }
 
     fn o_ptr(&mut self) -> OPtr<'_, BM::Page, BM::OlcEH> {
@@ -158,6 +436,39 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManagerGuard<'bm, BM> for SimpleGuardX
     }
 }
 
+impl<'bm, BM: CommonSeqLockBM<'bm>> SimpleGuardX<'bm, BM> {
+    /// must be called before unlocking
+    fn mark_if_written(&self) {
+        if self.written {
+            self.bm.mark_dirty(self.page_id());
+        }
+    }
+}
+
+impl<'bm, BM: CommonSeqLockBM<'bm>> SimpleGuardX<'bm, BM>
+where
+    BM::Page: Pod,
+{
+    /// Writes `bytes` at `offset` of the page and logs the change as part of `txn`.
+    /// Sets the page LSN to the LSN of the new record.
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if the bytes do not fit into the page.
+    /// If the record cannot be appended, the page is left unchanged and is not marked dirty.
+    pub fn write_logged(&mut self, txn: u64, offset: usize, bytes: &[u8]) -> io::Result<Lsn> {
+        let wal = self.bm.wal().expect("buffer manager has no write-ahead log");
+        let page_id = self.page_id();
+        let end = offset.checked_add(bytes.len()).filter(|&end| end <= size_of::<BM::Page>());
+        let (Some(end), Ok(log_offset)) = (end, u32::try_from(offset)) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "write outside the page"));
+        };
+        // only borrow the page mutably once the record is appended, that marks it as written
+        let undo = bytemuck::bytes_of(&**self)[offset..end].to_vec();
+        let lsn = wal.append(&LogRecord::Update { txn, page_id, offset: log_offset, undo, redo: bytes.to_vec() })?;
+        bytemuck::bytes_of_mut(&mut **self)[offset..end].copy_from_slice(bytes);
+        self.bm.set_page_lsn(page_id, lsn);
+        Ok(lsn)
+    }
+}
+
 impl<'bm, BM: CommonSeqLockBM<'bm>> ExclusiveGuard<'bm, BM> for SimpleGuardX<'bm, BM> {
     fn reset_written(&mut self) {
         self.written = false;
@@ -191,17 +502,17 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManager<'bm> for BM {
     type GuardS = SimpleGuardS<'bm, Self>;
     type GuardX = SimpleGuardX<'bm, Self>;
 
-    fn alloc(self) -> Self::GuardX {
-        let pid = self.alloc();
-        SimpleGuardX { bm: self, ptr: unsafe { &mut *self.page(pid).get() }, written: false }
+    fn try_alloc(self) -> Result<Self::GuardX, AllocError> {
+        let pid = CommonSeqLockBM::try_alloc(self)?;
+        Ok(SimpleGuardX { bm: self, pid, ptr: unsafe { &mut *self.page(pid).get() }, written: false })
     }
 }
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardUpgrade<'bm, BM, SimpleGuardS<'bm, BM>> for SimpleGuardO<'bm, BM> {
     fn upgrade(self) -> SimpleGuardS<'bm, BM> {
-        let pid = self.bm.pid_from_address(self.ptr.to_raw().addr());
+        let pid = self.pid;
         BM::OlcEH::optmistic_fail_check(self.bm.lock(pid).lock_shared(self.version));
-        let ret = SimpleGuardS { bm: self.bm, ptr: unsafe { &*self.bm.page(pid).get() } };
+        let ret = SimpleGuardS { bm: self.bm, pid, ptr: unsafe { &*self.bm.page(pid).get() } };
         self.release_unchecked();
         ret
     }
@@ -209,23 +520,79 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardUpgrade<'bm, BM, SimpleGuar
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardUpgrade<'bm, BM, SimpleGuardX<'bm, BM>> for SimpleGuardO<'bm, BM> {
     fn upgrade(self) -> SimpleGuardX<'bm, BM> {
-        let pid = self.bm.pid_from_address(self.ptr.to_raw().addr());
+        let pid = self.pid;
         BM::OlcEH::optmistic_fail_check(self.bm.lock(pid).lock_exclusive(self.version));
-        let ret = SimpleGuardX { bm: self.bm, ptr: unsafe { &mut *self.bm.page(pid).get() }, written: false };
+        let ret = SimpleGuardX { bm: self.bm, pid, ptr: unsafe { &mut *self.bm.page(pid).get() }, written: false };
         self.release_unchecked();
         ret
     }
 }
 
+impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardTryUpgrade<'bm, BM, SimpleGuardX<'bm, BM>>
+    for SimpleGuardS<'bm, BM>
+{
+    fn try_upgrade(self) -> Result<SimpleGuardX<'bm, BM>, Self> {
+        let pid = self.page_id();
+        if self.bm.lock(pid).try_upgrade_shared_to_exclusive().is_none() {
+            return Err(self);
+        }
+        let ret = SimpleGuardX { bm: self.bm, pid, ptr: unsafe { &mut *self.bm.page(pid).get() }, written: false };
+        forget(self);
+        Ok(ret)
+    }
+
+    fn upgrade_validated(self) -> Option<SimpleGuardX<'bm, BM>> {
+        match self.try_upgrade() {
+            Ok(x) => Some(x),
+            Err(s) => {
+                let (bm, pid) = (s.bm, s.page_id());
+                let version = s.release();
+                SimpleGuardX::acquire_wait_version(bm, pid, version)
+            }
+        }
+    }
+}
+
+impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardDowngrade<'bm, BM, SimpleGuardS<'bm, BM>>
+    for SimpleGuardX<'bm, BM>
+{
+    fn downgrade(self) -> SimpleGuardS<'bm, BM> {
+        let pid = self.page_id();
+        self.mark_if_written();
+        self.bm.lock(pid).downgrade_exclusive_to_shared();
+        let ret = SimpleGuardS { bm: self.bm, pid, ptr: unsafe { &*self.bm.page(pid).get() } };
+        forget(self);
+        ret
+    }
+}
+
+impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardDowngrade<'bm, BM, SimpleGuardO<'bm, BM>>
+    for SimpleGuardX<'bm, BM>
+{
+    fn downgrade(self) -> SimpleGuardO<'bm, BM> {
+        let (bm, pid) = (self.bm, self.page_id());
+        let version = self.release();
+        SimpleGuardO { bm, pid, ptr: unsafe { OPtr::from_raw(bm.page(pid).get()) }, version }
+    }
+}
+
+impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManageGuardDowngrade<'bm, BM, SimpleGuardO<'bm, BM>>
+    for SimpleGuardS<'bm, BM>
+{
+    fn downgrade(self) -> SimpleGuardO<'bm, BM> {
+        let (bm, pid) = (self.bm, self.page_id());
+        let version = self.release();
+        SimpleGuardO { bm, pid, ptr: unsafe { OPtr::from_raw(bm.page(pid).get()) }, version }
+    }
+}
+
 impl<'bm, BM: CommonSeqLockBM<'bm>> OptimisticGuard<'bm, BM> for SimpleGuardO<'bm, BM> {
     fn release_unchecked(self) {
         forget(self);
     }
 
     fn check(&self) -> OlcVersion {
-        BM::OlcEH::optmistic_fail_check(
-            self.bm.lock(self.bm.pid_from_address(self.ptr.to_raw().addr())).try_unlock_optimistic(self.version),
-        );
+        BM::OlcEH::optmistic_fail_check(self.bm.lock(self.pid).try_unlock_optimistic(self.version));
         self.version
     }
 
@@ -236,7 +603,7 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> OptimisticGuard<'bm, BM> for SimpleGuardO<'b
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> Drop for SimpleGuardO<'bm, BM> {
     fn drop(&mut self) {
-        match self.bm.lock(self.bm.pid_from_address(self.ptr.to_raw().addr())).try_unlock_optimistic(self.version) {
+        match self.bm.lock(self.pid).try_unlock_optimistic(self.version) {
             Ok(_) => (),
             Err(e) => {
                 if !BM::OlcEH::is_unwinding() {
@@ -258,19 +625,75 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> Drop for SimpleGuardX<'bm, BM> {
         if BM::OlcEH::is_unwinding() {
             assert!(!self.written);
         }
+        self.mark_if_written();
         self.bm.lock(self.page_id()).unlock_exclusive();
     }
 }
 
 impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManagerGuard<'bm, BM> for SimpleGuardO<'bm, BM> {
     fn acquire_wait(bm: BM, page_id: PageId) -> Self {
-        let Ok(version) = bm.lock(page_id).lock_optimistic(());
-        SimpleGuardO { bm, ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) }, version }
+        loop {
+            bm.fix(page_id, None);
+            let Ok(version) = bm.lock(page_id).lock_optimistic(());
+            if bm.is_resident(page_id) {
+                return SimpleGuardO {
+                    bm,
+                    pid: page_id,
+                    ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) },
+                    version,
+                };
+            }
+        }
     }
 
     fn acquire_wait_version(bm: BM, page_id: PageId, version: OlcVersion) -> Option<Self> {
+        bm.fix(page_id, None);
         bm.lock(page_id).lock_optimistic(version).ok()?;
-        Some(SimpleGuardO { bm, ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) }, version })
+        if !bm.is_resident(page_id) {
+            return None;
+        }
+        Some(SimpleGuardO { bm, pid: page_id, ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) }, version })
+    }
+
+    fn try_acquire(bm: BM, page_id: PageId) -> Option<Self> {
+        if !bm.fix(page_id, Some(Instant::now())) {
+            return None;
+        }
+        let version = bm.lock(page_id).try_lock_optimistic(())?;
+        if !bm.is_resident(page_id) {
+            return None;
+        }
+        Some(SimpleGuardO { bm, pid: page_id, ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) }, version })
+    }
+
+    fn try_acquire_version(bm: BM, page_id: PageId, version: OlcVersion) -> Option<Self> {
+        if !bm.fix(page_id, Some(Instant::now())) {
+            return None;
+        }
+        bm.lock(page_id).try_lock_optimistic(version)?;
+        if !bm.is_resident(page_id) {
+            return None;
+        }
+        Some(SimpleGuardO { bm, pid: page_id, ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) }, version })
+    }
+
+    fn acquire_timeout(bm: BM, page_id: PageId, timeout: Duration) -> Result<Self, LockTimeout> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !bm.fix(page_id, Some(deadline)) {
+                return Err(LockTimeout);
+            }
+            let version =
+                bm.lock(page_id).lock_optimistic_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            if bm.is_resident(page_id) {
+                return Ok(SimpleGuardO {
+                    bm,
+                    pid: page_id,
+                    ptr: unsafe { OPtr::from_raw(bm.page(page_id).get()) },
+                    version,
+                });
+            }
+        }
     }
 
     fn release(self) -> OlcVersion {
@@ -281,7 +704,7 @@ impl<'bm, BM: CommonSeqLockBM<'bm>> BufferManagerGuard<'bm, BM> for SimpleGuardO
     }
 
     fn page_id(&self) -> PageId {
-        self.bm.pid_from_address(self.ptr.to_raw() as usize)
+        self.pid
     }
 
     fn o_ptr(&mut self) -> OPtr<'_, BM::Page, BM::OlcEH> {
@@ -0,0 +1,45 @@
+use crate::PageId;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// One bit per page, set when an exclusive guard that wrote to the page is released.
+///
+/// Bits are set while the page is locked exclusively.
+/// Buffer managers with a file only clear them while writing the page back under a lock,
+/// so a clear bit means the file is up to date for anyone holding the page lock.
+/// [`SimpleBm`](crate::SimpleBm) clears them on request without locking the page,
+/// a write that is in progress at that time sets the bit again when its guard is released.
+pub(crate) struct DirtySet {
+    words: Box<[AtomicU64]>,
+}
+
+impl DirtySet {
+    pub fn new(capacity: usize) -> Self {
+        DirtySet { words: (0..capacity.div_ceil(64)).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    pub fn insert(&self, index: usize) {
+        self.words[index / 64].fetch_or(1 << (index % 64), Relaxed);
+    }
+
+    /// returns whether the bit was set
+    pub fn remove(&self, index: usize) -> bool {
+        let mask = 1 << (index % 64);
+        self.words[index / 64].fetch_and(!mask, Relaxed) & mask != 0
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        self.words[index / 64].load(Relaxed) & (1 << (index % 64)) != 0
+    }
+
+    /// Appends the set bits to `out` as page ids starting at `first`, clearing them if `clear` is set.
+    pub fn collect(&self, clear: bool, first: usize, out: &mut Vec<PageId>) {
+        for (i, word) in self.words.iter().enumerate() {
+            let mut bits = if clear { word.swap(0, Relaxed) } else { word.load(Relaxed) };
+            while bits != 0 {
+                out.push(PageId { x: (first + i * 64 + bits.trailing_zeros() as usize) as u64 });
+                bits &= bits - 1;
+            }
+        }
+    }
+}
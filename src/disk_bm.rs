@@ -0,0 +1,574 @@
+use crate::buffer_manager::CommonSeqLockBM;
+use crate::dirty_set::DirtySet;
+use crate::seqlock::{PageLock, SeqLock};
+use crate::wal::{LogRecord, Lsn, Wal};
+use crate::{AllocError, BufferManager, BufferManagerExt, PageId, UnwindOlcEh};
+use bytemuck::{bytes_of, bytes_of_mut, Pod, Zeroable};
+use std::cell::UnsafeCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+const NO_PAGE: u64 = u64::MAX;
+const NO_FRAME: usize = usize::MAX;
+/// number of full clock rotations before giving up on finding an evictable frame
+const EVICT_ROUNDS: usize = 8;
+
+/// A buffer manager that keeps pages in a file and caches a bounded number of them in memory.
+///
+/// Pages are evicted using the clock algorithm and written back on eviction if they are dirty,
+/// that is, if they were written through an exclusive guard since they were last written back.
+/// Page `n` is stored at offset `n * size_of::<P>()` in the file.
+/// Pages deallocated during one run are not reused after reopening the file.
+///
+/// If opened with a [`Wal`], each page is followed by its [`Lsn`] in the file, so page `n` is stored at offset
+/// `n * (size_of::<P>() + 8)`. The LSN is kept outside the page, so all of the page is available to its users,
+/// and the log is flushed up to it before the page is written back.
+pub struct DiskBm<P, L: PageLock = SeqLock> {
+    frames: Box<[UnsafeCell<P>]>,
+    frame_pid: Box<[AtomicU64]>,
+    /// LSN of the page in each frame, only maintained with a write-ahead log
+    frame_lsn: Box<[AtomicU64]>,
+    frame_referenced: Box<[AtomicBool]>,
+    page_frame: Box<[AtomicUsize]>,
+    locks: Box<[L]>,
+    dirty: DirtySet,
+    free_frames: Mutex<Vec<usize>>,
+    /// deallocated pages with the LSN of their last change
+    free_pages: Mutex<Vec<(u64, Lsn)>>,
+    page_count: AtomicU64,
+    clock_hand: AtomicUsize,
+    file: File,
+    wal: Option<Wal>,
+}
+
+unsafe impl<P, L: PageLock> Sync for DiskBm<P, L> {}
+
+impl<P: Zeroable, L: PageLock> DiskBm<P, L> {
+    /// Opens or creates the page file at `path`.
+    /// `pool_size` is the number of pages kept in memory, `capacity` the maximum number of pages in the file.
+    pub fn open(path: impl AsRef<Path>, pool_size: usize, capacity: usize) -> io::Result<Self> {
+        Self::open_inner(path, pool_size, capacity, None)
+    }
+
+    /// Like [`open`](Self::open), but logs changes to the write-ahead log at `wal_path`.
+    pub fn open_with_wal(
+        path: impl AsRef<Path>,
+        wal_path: impl AsRef<Path>,
+        pool_size: usize,
+        capacity: usize,
+    ) -> io::Result<Self> {
+        Self::open_inner(path, pool_size, capacity, Some(Wal::open(wal_path)?))
+    }
+
+    fn open_inner(path: impl AsRef<Path>, pool_size: usize, capacity: usize, wal: Option<Wal>) -> io::Result<Self> {
+        assert!(pool_size > 0);
+        assert!(size_of::<P>() > 0);
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let page_count = file.metadata()?.len().div_ceil(slot_size::<P>(wal.is_some()));
+        if page_count > capacity as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "page file exceeds capacity"));
+        }
+        unsafe {
+            Ok(DiskBm {
+                frames: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(pool_size)),
+                frame_pid: (0..pool_size).map(|_| AtomicU64::new(NO_PAGE)).collect(),
+                frame_lsn: (0..pool_size).map(|_| AtomicU64::new(0)).collect(),
+                frame_referenced: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(pool_size)),
+                page_frame: (0..capacity).map(|_| AtomicUsize::new(NO_FRAME)).collect(),
+                locks: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(capacity)),
+                dirty: DirtySet::new(capacity),
+                free_frames: Mutex::new((0..pool_size).collect()),
+                free_pages: Mutex::new(Vec::new()),
+                page_count: AtomicU64::new(page_count),
+                clock_hand: AtomicUsize::new(0),
+                file,
+                wal,
+            })
+        }
+    }
+}
+
+/// space taken by a page in the file
+fn slot_size<P>(logged: bool) -> u64 {
+    (size_of::<P>() + if logged { size_of::<Lsn>() } else { 0 }) as u64
+}
+
+impl<P: Pod, L: PageLock> DiskBm<P, L> {
+    /// Brings the pages to a transaction consistent state after a crash, using the write-ahead log.
+    ///
+    /// All logged changes are redone unless the page LSN shows they already reached the page file.
+    /// Then the updates of transactions that neither committed nor aborted are undone in reverse order.
+    /// Each undo is logged as a compensation, so recovery can itself be interrupted by a crash.
+    /// A transaction id may be used again once its transaction has committed or aborted.
+    /// If there is a [`checkpoint`](Self::checkpoint), only the log after the oldest change it lists is read.
+    /// Must be called after opening, before the buffer manager is used otherwise.
+    pub fn recover(&self) -> io::Result<()> {
+        let Some(wal) = &self.wal else {
+            return Ok(());
+        };
+        let mut active = HashSet::new();
+        let (records, analysis_start) = match wal.last_checkpoint() {
+            Some(checkpoint) => {
+                let Some((_, LogRecord::Checkpoint { begin, dirty_pages, active_txns })) =
+                    wal.records_from(checkpoint)?.into_iter().next()
+                else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "missing checkpoint record"));
+                };
+                active.extend(active_txns.iter().map(|&(txn, _)| txn));
+                // older changes are on disk and older transactions have ended
+                let start = dirty_pages.iter().map(|&(_, lsn)| lsn).chain(active_txns.iter().map(|&(_, lsn)| lsn));
+                (wal.records_from(start.fold(begin, Lsn::min))?, begin)
+            }
+            None => (wal.records()?, Lsn { x: 0 }),
+        };
+        for (lsn, record) in &records {
+            match record {
+                LogRecord::Update { txn, page_id, .. } | LogRecord::Compensation { txn, page_id, .. } => {
+                    if *lsn >= analysis_start {
+                        active.insert(*txn);
+                    }
+                    if page_id.x >= self.locks.len() as u64 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "log refers to page beyond capacity"));
+                    }
+                    // the page may have been allocated but never written back
+                    self.page_count.fetch_max(page_id.x + 1, Relaxed);
+                }
+                LogRecord::Commit { txn } | LogRecord::Abort { txn } if *lsn >= analysis_start => {
+                    active.remove(txn);
+                }
+                _ => (),
+            }
+        }
+        for (lsn, record) in &records {
+            let (LogRecord::Update { page_id, offset, redo, .. }
+            | LogRecord::Compensation { page_id, offset, redo, .. }) = record
+            else {
+                continue;
+            };
+            let mut page = self.lock_loaded(*page_id)?;
+            let frame = self.frame_of(*page_id).unwrap();
+            if self.frame_lsn[frame].load(Relaxed) < lsn.x {
+                bytes_of_mut(&mut *page)[*offset as usize..][..redo.len()].copy_from_slice(redo);
+                self.frame_lsn[frame].store(lsn.x, Relaxed);
+                wal.mark_dirty(*page_id, *lsn);
+            }
+        }
+        // updates at or after this LSN were undone before the crash
+        let mut undone_from = HashMap::<u64, Lsn>::new();
+        // transaction ids may be reused, records before the last commit or abort of an id belong to an ended transaction
+        let mut undoing = active.clone();
+        for (lsn, record) in records.iter().rev() {
+            match record {
+                LogRecord::Commit { txn } | LogRecord::Abort { txn } => {
+                    undoing.remove(txn);
+                }
+                LogRecord::Compensation { txn, compensated, .. } if undoing.contains(txn) => {
+                    let undone = undone_from.entry(*txn).or_insert(*compensated);
+                    *undone = (*undone).min(*compensated);
+                }
+                LogRecord::Update { txn, page_id, offset, undo, .. }
+                    if undoing.contains(txn) && undone_from.get(txn).is_none_or(|undone| lsn < undone) =>
+                {
+                    let mut page = self.lock_loaded(*page_id)?;
+                    let compensation = LogRecord::Compensation {
+                        txn: *txn,
+                        page_id: *page_id,
+                        offset: *offset,
+                        compensated: *lsn,
+                        redo: undo.clone(),
+                    };
+                    let compensation = wal.append(&compensation)?;
+                    bytes_of_mut(&mut *page)[*offset as usize..][..undo.len()].copy_from_slice(undo);
+                    self.frame_lsn[self.frame_of(*page_id).unwrap()].store(compensation.x, Relaxed);
+                }
+                _ => (),
+            }
+        }
+        for txn in active {
+            wal.append(&LogRecord::Abort { txn })?;
+        }
+        wal.flush_all()
+    }
+
+    /// Like `lock_exclusive`, but returns errors from loading the page instead of panicking.
+    fn lock_loaded(&self, pid: PageId) -> io::Result<<&Self as BufferManager<'_>>::GuardX> {
+        let Ok(_) = self.locks[pid.x as usize].lock_exclusive(());
+        if !self.load_and_unlock(pid)? {
+            return Err(io::Error::other(AllocError::PoolExhausted.to_string()));
+        }
+        // nothing else runs during recovery, so the page is still resident
+        Ok(self.lock_exclusive(pid))
+    }
+}
+
+impl<P, L: PageLock> DiskBm<P, L> {
+    /// Writes all dirty resident pages back to the file and syncs it.
+    pub fn flush(&self) -> io::Result<()> {
+        for (frame, pid) in self.frame_pid.iter().enumerate() {
+            let pid = pid.load(Relaxed);
+            if pid == NO_PAGE {
+                continue;
+            }
+            let Ok(_) = self.locks[pid as usize].lock_shared(());
+            let result =
+                if self.page_frame[pid as usize].load(Relaxed) == frame { self.write_back(frame, pid) } else { Ok(()) };
+            self.locks[pid as usize].unlock_shared();
+            result?;
+        }
+        self.file.sync_data()
+    }
+
+    pub fn wal(&self) -> Option<&Wal> {
+        self.wal.as_ref()
+    }
+
+    /// Writes `pid` back to the file if it is dirty, returns whether it was.
+    /// Unlike [`flush`](Self::flush), this does not sync the file.
+    pub fn flush_page(&self, pid: PageId) -> io::Result<bool> {
+        let lock = &self.locks[pid.x as usize];
+        let Ok(_) = lock.lock_shared(());
+        // pages are written back when they are evicted, so only resident pages can be dirty
+        let dirty = self.dirty.contains(pid.x as usize);
+        let result = match self.frame_of(pid) {
+            Some(frame) if dirty => self.write_back(frame, pid.x),
+            _ => Ok(()),
+        };
+        lock.unlock_shared();
+        result.map(|()| dirty)
+    }
+
+    /// Returns the pages that were written since they were last written back.
+    /// Their dirty bits are cleared by writing them back with [`flush`](Self::flush) or [`flush_page`](Self::flush_page).
+    pub fn dirty_pages(&self) -> Vec<PageId> {
+        let mut dirty = Vec::new();
+        self.dirty.collect(false, 0, &mut dirty);
+        dirty
+    }
+
+    /// Writes the page in `frame` to the file if it is dirty, obeying the write-ahead rule.
+    /// Requires a lock on `pid`.
+    fn write_back(&self, frame: usize, pid: u64) -> io::Result<()> {
+        if !self.dirty.contains(pid as usize) {
+            return Ok(());
+        }
+        match &self.wal {
+            Some(wal) => {
+                let lsn = Lsn { x: self.frame_lsn[frame].load(Relaxed) };
+                wal.flush(lsn)?;
+                self.write_slot(pid, self.frame_bytes(frame), lsn)?;
+            }
+            None => self.file.write_all_at(self.frame_bytes(frame), self.file_offset(pid))?,
+        }
+        self.dirty.remove(pid as usize);
+        if let Some(wal) = &self.wal {
+            wal.page_written(PageId { x: pid });
+        }
+        Ok(())
+    }
+
+    /// Takes a fuzzy checkpoint, bounding the part of the log read by [`recover`](Self::recover).
+    ///
+    /// Dirty pages are written back one at a time under a shared lock while other threads keep modifying pages,
+    /// then the remaining dirty pages and active transactions are logged.
+    /// May be called periodically from a background thread.
+    pub fn checkpoint(&self) -> io::Result<Lsn> {
+        let wal = self.wal.as_ref().expect("checkpoints require a write-ahead log");
+        for (pid, _) in wal.dirty_pages() {
+            let lock = &self.locks[pid.x as usize];
+            let Ok(_) = lock.lock_shared(());
+            // evicted pages were written back by eviction.
+            // Writing while locked keeps an older image from overwriting a newer one written back concurrently.
+            let result = match self.frame_of(pid) {
+                Some(frame) => self.write_back(frame, pid.x),
+                None => Ok(()),
+            };
+            lock.unlock_shared();
+            result?;
+        }
+        let checkpoint = wal.begin_checkpoint();
+        // pages written back before the snapshot, including by eviction, are durable before it is logged
+        self.file.sync_data()?;
+        wal.end_checkpoint(&checkpoint)
+    }
+
+    fn file_offset(&self, pid: u64) -> u64 {
+        pid * slot_size::<P>(self.wal.is_some())
+    }
+
+    /// Writes a page followed by its LSN with a single write.
+    fn write_slot(&self, pid: u64, page: &[u8], lsn: Lsn) -> io::Result<()> {
+        let mut slot = Vec::with_capacity(page.len() + size_of::<Lsn>());
+        slot.extend_from_slice(page);
+        slot.extend_from_slice(bytes_of(&lsn));
+        self.file.write_all_at(&slot, self.file_offset(pid))
+    }
+
+    /// Requires a lock on the page in `frame`.
+    fn frame_bytes(&self, frame: usize) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.frames[frame].get() as *const u8, size_of::<P>()) }
+    }
+
+    /// Requires an exclusive lock on the page in `frame`, or that the frame is not mapped.
+    #[allow(clippy::mut_from_ref)]
+    fn frame_bytes_mut(&self, frame: usize) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.frames[frame].get() as *mut u8, size_of::<P>()) }
+    }
+
+    fn frame_of(&self, pid: PageId) -> Option<usize> {
+        let frame = self.page_frame[pid.x as usize].load(Acquire);
+        (frame != NO_FRAME).then_some(frame)
+    }
+
+    /// requires exclusive lock on pid
+    fn map_frame(&self, pid: PageId, frame: usize) {
+        self.frame_pid[frame].store(pid.x, Relaxed);
+        self.frame_referenced[frame].store(true, Relaxed);
+        self.page_frame[pid.x as usize].store(frame, Release);
+    }
+
+    /// Loads `pid` if it is not resident and releases the exclusive lock on it.
+    /// Returns false if all resident pages are locked, so none can be evicted to make room.
+    fn load_and_unlock(&self, pid: PageId) -> io::Result<bool> {
+        let lock = &self.locks[pid.x as usize];
+        if self.frame_of(pid).is_some() {
+            lock.unlock_exclusive_unmodified();
+            return Ok(true);
+        }
+        let loaded = self.load(pid);
+        if let Ok(true) = loaded {
+            lock.unlock_exclusive();
+        } else {
+            lock.unlock_exclusive_unmodified();
+        }
+        loaded
+    }
+
+    /// Returns false if all resident pages are locked.
+    /// requires exclusive lock on pid
+    fn load(&self, pid: PageId) -> io::Result<bool> {
+        let Some(frame) = self.get_frame()? else {
+            return Ok(false);
+        };
+        if let Err(e) = self.read_frame(pid, frame) {
+            self.free_frames.lock().unwrap().push(frame);
+            return Err(e);
+        }
+        self.map_frame(pid, frame);
+        Ok(true)
+    }
+
+    /// requires exclusive lock on pid
+    fn read_frame(&self, pid: PageId, frame: usize) -> io::Result<()> {
+        read_page(&self.file, self.frame_bytes_mut(frame), self.file_offset(pid.x))?;
+        if self.wal.is_some() {
+            let mut lsn = Lsn { x: 0 };
+            read_page(&self.file, bytes_of_mut(&mut lsn), self.file_offset(pid.x) + size_of::<P>() as u64)?;
+            self.frame_lsn[frame].store(lsn.x, Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Returns a free frame, evicting a page if necessary, or `None` if all resident pages are locked.
+    fn get_frame(&self) -> io::Result<Option<usize>> {
+        if let Some(frame) = self.free_frames.lock().unwrap().pop() {
+            return Ok(Some(frame));
+        }
+        for _ in 0..self.frames.len() * EVICT_ROUNDS {
+            let frame = self.clock_hand.fetch_add(1, Relaxed) % self.frames.len();
+            if self.frame_referenced[frame].swap(false, Relaxed) {
+                continue;
+            }
+            let pid = self.frame_pid[frame].load(Relaxed);
+            if pid == NO_PAGE {
+                continue;
+            }
+            let lock = &self.locks[pid as usize];
+            if lock.try_lock_exclusive(()).is_none() {
+                continue;
+            }
+            if self.page_frame[pid as usize].load(Relaxed) != frame {
+                // the frame was reassigned in the meantime, optimistic readers of the page remain valid
+                lock.unlock_exclusive_unmodified();
+                continue;
+            }
+            if let Err(e) = self.write_back(frame, pid) {
+                // the page stays resident and dirty
+                lock.unlock_exclusive_unmodified();
+                return Err(e);
+            }
+            self.page_frame[pid as usize].store(NO_FRAME, Relaxed);
+            self.frame_pid[frame].store(NO_PAGE, Relaxed);
+            lock.unlock_exclusive();
+            return Ok(Some(frame));
+        }
+        Ok(None)
+    }
+}
+
+/// Reads a page from `file`, zero filling whatever lies beyond the end of the file.
+pub(crate) fn read_page(file: &File, bytes: &mut [u8], offset: u64) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match file.read_at(&mut bytes[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    // the page was allocated but never written back
+    bytes[filled..].fill(0);
+    Ok(())
+}
+
+impl<P, L: PageLock> Drop for DiskBm<P, L> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            if !std::thread::panicking() {
+                panic!("failed to flush pages: {e}");
+            }
+        }
+    }
+}
+
+impl<'bm, P, L: PageLock> CommonSeqLockBM<'bm> for &'bm DiskBm<P, L> {
+    type Page = P;
+    type OlcEH = UnwindOlcEh;
+    type Lock = L;
+
+    fn pid_from_address(self, address: usize) -> PageId {
+        let start = self.frames.as_ptr().addr();
+        debug_assert!(address >= start);
+        debug_assert!(address < start + size_of::<P>() * self.frames.len());
+        let offset = address - start;
+        assert_eq!(offset % size_of::<P>(), 0);
+        PageId { x: self.frame_pid[offset / size_of::<P>()].load(Relaxed) }
+    }
+
+    fn try_alloc(self) -> Result<PageId, AllocError> {
+        let (pid, lsn) = {
+            let mut free_pages = self.free_pages.lock().unwrap();
+            match free_pages.pop() {
+                Some(free) => free,
+                None => {
+                    let pid = self.page_count.load(Relaxed);
+                    if pid >= self.locks.len() as u64 {
+                        return Err(AllocError::OutOfPages);
+                    }
+                    self.page_count.store(pid + 1, Relaxed);
+                    (pid, Lsn { x: 0 })
+                }
+            }
+        };
+        let pid = PageId { x: pid };
+        // not force_lock_exclusive, eviction may still hold the lock of a deallocated page
+        let Ok(_) = self.locks[pid.x as usize].lock_exclusive(());
+        // an optimistic reader with a stale page id may have loaded the freed page again
+        let frame = match self.frame_of(pid).map_or_else(|| self.get_frame(), |frame| Ok(Some(frame))) {
+            Ok(Some(frame)) => Ok(frame),
+            Ok(None) => Err(AllocError::PoolExhausted),
+            Err(e) => Err(AllocError::Io(e.kind())),
+        };
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.locks[pid.x as usize].unlock_exclusive();
+                self.free_pages.lock().unwrap().push((pid.x, lsn));
+                return Err(e);
+            }
+        };
+        self.frame_bytes_mut(frame).fill(0);
+        // a reused page keeps the LSN of its last change, so recovery does not redo changes from before it was freed
+        self.frame_lsn[frame].store(lsn.x, Relaxed);
+        // the file may still hold the contents of a deallocated page
+        self.dirty.insert(pid.x as usize);
+        self.map_frame(pid, frame);
+        Ok(pid)
+    }
+
+    fn dealloc(self, pid: PageId) {
+        let mut lsn = Lsn { x: 0 };
+        if let Some(frame) = self.frame_of(pid) {
+            lsn.x = self.frame_lsn[frame].load(Relaxed);
+            self.page_frame[pid.x as usize].store(NO_FRAME, Relaxed);
+            self.frame_pid[frame].store(NO_PAGE, Relaxed);
+            self.free_frames.lock().unwrap().push(frame);
+        }
+        self.dirty.remove(pid.x as usize);
+        if let Some(wal) = &self.wal {
+            // changes to a freed page need not reach the file, so they must not hold back checkpoints
+            wal.page_written(pid);
+        }
+        self.locks[pid.x as usize].unlock_exclusive();
+        self.free_pages.lock().unwrap().push((pid.x, lsn));
+    }
+
+    fn page(self, pid: PageId) -> &'bm UnsafeCell<Self::Page> {
+        // optimistic readers may race with eviction, they fail validation later
+        &self.frames[self.frame_of(pid).unwrap_or(0)]
+    }
+
+    fn lock(self, pid: PageId) -> &'bm L {
+        &self.locks[pid.x as usize]
+    }
+
+    fn fix(self, pid: PageId, deadline: Option<Instant>) -> bool {
+        loop {
+            if let Some(frame) = self.frame_of(pid) {
+                self.frame_referenced[frame].store(true, Relaxed);
+                return true;
+            }
+            let lock = &self.locks[pid.x as usize];
+            if lock.try_lock_exclusive(()).is_some() {
+                match self.load_and_unlock(pid) {
+                    Ok(true) => return true,
+                    Ok(false) => {
+                        // there is nothing to park on until a resident page is unlocked
+                        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            return false;
+                        }
+                        std::thread::yield_now();
+                        continue;
+                    }
+                    Err(e) => panic!("failed to load page {}: {e}", pid.x),
+                }
+            }
+            // another thread is loading or evicting the page, park until it is done
+            let waited = match deadline {
+                Some(deadline) => lock.lock_shared(deadline).is_ok(),
+                None => {
+                    let Ok(_) = lock.lock_shared(());
+                    true
+                }
+            };
+            if !waited {
+                return false;
+            }
+            lock.unlock_shared();
+        }
+    }
+
+    fn is_resident(self, pid: PageId) -> bool {
+        self.frame_of(pid).is_some()
+    }
+
+    fn mark_dirty(self, pid: PageId) {
+        self.dirty.insert(pid.x as usize);
+    }
+
+    fn wal(self) -> Option<&'bm Wal> {
+        self.wal.as_ref()
+    }
+
+    fn set_page_lsn(self, pid: PageId, lsn: Lsn) {
+        self.frame_lsn[self.frame_of(pid).unwrap()].store(lsn.x, Relaxed);
+    }
+}
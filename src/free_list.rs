@@ -0,0 +1,60 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Mutex;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+std::thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Relaxed);
+}
+
+/// A set of free page ids split into independently locked shards.
+///
+/// Each thread pushes to and pops from its own shard and only touches other shards when its own is empty,
+/// so concurrent allocations usually do not contend.
+pub(crate) struct FreeList {
+    shards: Box<[Shard]>,
+}
+
+#[repr(align(128))]
+struct Shard(Mutex<Vec<usize>>);
+
+impl FreeList {
+    pub fn new() -> Self {
+        let shard_count = std::thread::available_parallelism().map_or(1, |n| n.get());
+        FreeList { shards: (0..shard_count).map(|_| Shard(Mutex::new(Vec::new()))).collect() }
+    }
+
+    fn home(&self) -> usize {
+        SHARD.with(|s| *s) % self.shards.len()
+    }
+
+    /// Returns `None` only if all shards were empty at the same time.
+    pub fn pop(&self) -> Option<usize> {
+        let home = self.home();
+        for i in 0..self.shards.len() {
+            let shard = &self.shards[(home + i) % self.shards.len()];
+            if let Some(x) = shard.0.lock().unwrap().pop() {
+                return Some(x);
+            }
+        }
+        // an id may have been pushed to a shard after we visited it, so look at all shards at once.
+        // Shards are locked in order and other operations hold at most one, so this cannot deadlock.
+        let mut shards: Vec<_> = self.shards.iter().map(|s| s.0.lock().unwrap()).collect();
+        shards.iter_mut().find_map(|s| s.pop())
+    }
+
+    pub fn push(&self, x: usize) {
+        self.shards[self.home()].0.lock().unwrap().push(x);
+    }
+
+    /// Distributes `range` over all shards.
+    pub fn extend(&self, range: std::ops::Range<usize>) {
+        let chunk = range.len().div_ceil(self.shards.len()).max(1);
+        for (i, shard) in self.shards.iter().enumerate() {
+            let start = (range.start + i * chunk).min(range.end);
+            let end = (start + chunk).min(range.end);
+            shard.0.lock().unwrap().extend(start..end);
+        }
+    }
+}
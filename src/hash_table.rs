@@ -0,0 +1,273 @@
+use crate::node::{write, NodeRead};
+use crate::{
+    AllocError, BufferManageGuardUpgrade, BufferManager, BufferManagerExt, BufferManagerGuard, ExclusiveGuard, OPtr,
+    OlcErrorHandler, OptimisticGuard, PageId,
+};
+use bytemuck::{bytes_of, bytes_of_mut, Pod};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::marker::PhantomData;
+
+const GLOBAL_DEPTH_OFFSET: usize = 0;
+const DIRECTORY_HEADER_SIZE: usize = 8;
+
+const COUNT_OFFSET: usize = 0;
+const LOCAL_DEPTH_OFFSET: usize = 2;
+/// page id of the next overflow page
+const NEXT_OFFSET: usize = 8;
+const BUCKET_HEADER_SIZE: usize = 16;
+const NO_PAGE: u64 = u64::MAX;
+
+/// A hash table with fixed size keys and values using extendible hashing.
+///
+/// The directory is a single page, buckets are split under exclusive locks until the directory is full.
+/// After that, full buckets are extended with a chain of overflow pages.
+/// Lookups only lock pages optimistically.
+/// Keys are hashed with [`DefaultHasher`], so a table reopened with [`HashTable::open`] must have been created
+/// by the same build.
+pub struct HashTable<'bm, BM: BufferManager<'bm>, K, V> {
+    bm: BM,
+    directory: PageId,
+    _p: PhantomData<&'bm (K, V)>,
+}
+
+impl<'bm, BM, K, V> HashTable<'bm, BM, K, V>
+where
+    BM: BufferManager<'bm>,
+    BM::Page: Pod,
+    K: Pod + Hash + Eq,
+    V: Pod,
+{
+    /// Allocates a directory page and a single bucket.
+    pub fn new(bm: BM) -> Result<Self, AllocError> {
+        assert!(Self::capacity() >= 1, "page too small for an entry");
+        assert!(Self::max_depth() >= 1, "page too small for a directory");
+        let mut bucket = bm.try_alloc()?;
+        init_bucket(bytes_of_mut(&mut *bucket), 0);
+        let mut directory = match bm.try_alloc() {
+            Ok(directory) => directory,
+            Err(e) => {
+                bucket.dealloc();
+                return Err(e);
+            }
+        };
+        let bytes = bytes_of_mut(&mut *directory);
+        write(bytes, GLOBAL_DEPTH_OFFSET, &0u64);
+        write(bytes, DIRECTORY_HEADER_SIZE, &bucket.page_id().x);
+        Ok(HashTable { bm, directory: directory.page_id(), _p: PhantomData })
+    }
+
+    /// Opens a table previously created with [`HashTable::new`].
+    pub fn open(bm: BM, directory: PageId) -> Self {
+        HashTable { bm, directory, _p: PhantomData }
+    }
+
+    pub fn directory_page_id(&self) -> PageId {
+        self.directory
+    }
+
+    fn page_bytes(guard: &BM::GuardO) -> OPtr<'bm, [u8], BM::OlcEH> {
+        guard.o_ptr_bm().as_slice::<u8>()
+    }
+
+    fn capacity() -> usize {
+        (size_of::<BM::Page>() - BUCKET_HEADER_SIZE) / (size_of::<K>() + size_of::<V>())
+    }
+
+    /// largest global depth for which the directory fits in a page
+    fn max_depth() -> u32 {
+        ((size_of::<BM::Page>() - DIRECTORY_HEADER_SIZE) / size_of::<u64>()).ilog2()
+    }
+
+    fn hash(key: K) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn directory_entry(directory: impl NodeRead, hash: u64) -> PageId {
+        let depth: u64 = directory.read(GLOBAL_DEPTH_OFFSET);
+        if depth > Self::max_depth() as u64 {
+            // only possible when reading optimistically while the directory is modified
+            BM::OlcEH::optimistic_fail();
+        }
+        let index = hash & ((1 << depth) - 1);
+        PageId { x: directory.read(DIRECTORY_HEADER_SIZE + index as usize * size_of::<u64>()) }
+    }
+
+    fn count(bucket: impl NodeRead) -> usize {
+        bucket.read::<u16>(COUNT_OFFSET) as usize
+    }
+
+    fn local_depth(bucket: impl NodeRead) -> u32 {
+        bucket.read::<u16>(LOCAL_DEPTH_OFFSET) as u32
+    }
+
+    fn next(bucket: impl NodeRead) -> Option<PageId> {
+        let next: u64 = bucket.read(NEXT_OFFSET);
+        (next != NO_PAGE).then_some(PageId { x: next })
+    }
+
+    fn key_offset(i: usize) -> usize {
+        BUCKET_HEADER_SIZE + i * size_of::<K>()
+    }
+
+    fn value_offset(i: usize) -> usize {
+        BUCKET_HEADER_SIZE + Self::capacity() * size_of::<K>() + i * size_of::<V>()
+    }
+
+    fn find(bucket: impl NodeRead, key: K) -> Option<usize> {
+        (0..Self::count(bucket)).find(|&i| bucket.read::<K>(Self::key_offset(i)) == key)
+    }
+
+    fn push(bucket: &mut [u8], key: K, value: V) {
+        let count = Self::count(&*bucket);
+        write(bucket, Self::key_offset(count), &key);
+        write(bucket, Self::value_offset(count), &value);
+        write(bucket, COUNT_OFFSET, &(count as u16 + 1));
+    }
+
+    /// Removes the entry at `i` by moving the last entry into its place, returns its value.
+    fn swap_remove(bucket: &mut [u8], i: usize) -> V {
+        let count = Self::count(&*bucket);
+        let value = bucket.read(Self::value_offset(i));
+        bucket.copy_within(Self::key_offset(count - 1)..Self::key_offset(count), Self::key_offset(i));
+        bucket.copy_within(Self::value_offset(count - 1)..Self::value_offset(count), Self::value_offset(i));
+        write(bucket, COUNT_OFFSET, &(count as u16 - 1));
+        value
+    }
+
+    /// Locks the first page of the bucket for `hash` optimistically.
+    fn lock_bucket(&self, hash: u64) -> BM::GuardO {
+        let directory = self.bm.lock_optimistic(self.directory);
+        let pid = Self::directory_entry(Self::page_bytes(&directory), hash);
+        directory.check();
+        let bucket = self.bm.lock_optimistic(pid);
+        // validating again ensures the bucket was not split before it was locked
+        directory.release();
+        bucket
+    }
+
+    pub fn lookup(&self, key: K) -> Option<V> {
+        let hash = Self::hash(key);
+        BM::repeat(|| {
+            let mut page = self.lock_bucket(hash);
+            loop {
+                let bytes = Self::page_bytes(&page);
+                if let Some(i) = Self::find(bytes, key) {
+                    let value = bytes.read(Self::value_offset(i));
+                    page.release();
+                    return Some(value);
+                }
+                let next = Self::next(bytes);
+                page.check();
+                let next = self.bm.lock_optimistic(next?);
+                // the link to the next page must still be valid now that it is locked
+                page.release();
+                page = next;
+            }
+        })
+    }
+
+    /// Locks all pages of the bucket for `hash` exclusively.
+    /// Pages are locked in chain order starting with the first one, so writers of a chain never interleave.
+    /// Optimistic readers validate each page before moving on to the next one.
+    fn lock_chain(&self, hash: u64) -> Vec<BM::GuardX> {
+        let mut chain: Vec<BM::GuardX> = vec![self.lock_bucket(hash).upgrade()];
+        while let Some(next) = Self::next(bytes_of(&**chain.last().unwrap())) {
+            chain.push(self.bm.lock_exclusive(next));
+        }
+        chain
+    }
+
+    /// Inserts or replaces the value for `key`, returns the previous value.
+    /// Fails if a bucket has to be split or extended and the buffer manager is out of pages,
+    /// the table is unchanged then.
+    pub fn insert(&self, key: K, value: V) -> Result<Option<V>, AllocError> {
+        let hash = Self::hash(key);
+        loop {
+            let mut chain = BM::repeat(|| self.lock_chain(hash));
+            for page in &mut chain {
+                if let Some(i) = Self::find(bytes_of(&**page), key) {
+                    let bytes = bytes_of_mut(&mut **page);
+                    let previous = bytes.read(Self::value_offset(i));
+                    write(bytes, Self::value_offset(i), &value);
+                    return Ok(Some(previous));
+                }
+            }
+            if let Some(page) = chain.iter_mut().find(|page| Self::count(bytes_of(&***page)) < Self::capacity()) {
+                Self::push(bytes_of_mut(&mut **page), key, value);
+                return Ok(None);
+            }
+            if Self::local_depth(bytes_of(&*chain[0])) < Self::max_depth() {
+                drop(chain);
+                self.split(hash)?;
+                continue;
+            }
+            // the chain was only read so far, so its guards release the pages unchanged on failure
+            let mut overflow = self.bm.try_alloc()?;
+            let bytes = bytes_of_mut(&mut *overflow);
+            init_bucket(bytes, Self::local_depth(bytes_of(&*chain[0])));
+            Self::push(bytes, key, value);
+            write(bytes_of_mut(&mut **chain.last_mut().unwrap()), NEXT_OFFSET, &overflow.page_id().x);
+            return Ok(None);
+        }
+    }
+
+    /// Splits the bucket for `hash` if it is still full, doubling the directory if necessary.
+    /// The sibling is allocated before anything is modified, so nothing changes if that fails.
+    fn split(&self, hash: u64) -> Result<(), AllocError> {
+        let mut directory = self.bm.lock_exclusive(self.directory);
+        let mut bucket = self.bm.lock_exclusive(Self::directory_entry(bytes_of(&*directory), hash));
+        let local_depth = Self::local_depth(bytes_of(&*bucket));
+        if Self::count(bytes_of(&*bucket)) < Self::capacity() || local_depth >= Self::max_depth() {
+            return Ok(());
+        }
+        let mut sibling = self.bm.try_alloc()?;
+        let directory = bytes_of_mut(&mut *directory);
+        let global_depth = directory.read::<u64>(GLOBAL_DEPTH_OFFSET) as u32;
+        if local_depth == global_depth {
+            let len = size_of::<u64>() << global_depth;
+            directory.copy_within(DIRECTORY_HEADER_SIZE..DIRECTORY_HEADER_SIZE + len, DIRECTORY_HEADER_SIZE + len);
+            write(directory, GLOBAL_DEPTH_OFFSET, &(global_depth as u64 + 1));
+        }
+        let sibling_pid = sibling.page_id();
+        let sibling = bytes_of_mut(&mut *sibling);
+        init_bucket(sibling, local_depth + 1);
+        let bucket = bytes_of_mut(&mut *bucket);
+        write(bucket, LOCAL_DEPTH_OFFSET, &(local_depth as u16 + 1));
+        for i in (0..Self::count(&*bucket)).rev() {
+            let key: K = bucket.read(Self::key_offset(i));
+            if Self::hash(key) >> local_depth & 1 == 1 {
+                let value = Self::swap_remove(bucket, i);
+                Self::push(sibling, key, value);
+            }
+        }
+        let global_depth = directory.read::<u64>(GLOBAL_DEPTH_OFFSET);
+        let local_mask = (1 << local_depth) - 1;
+        let sibling_bits = (hash & local_mask) | 1 << local_depth;
+        for i in 0..1u64 << global_depth {
+            if i & (local_mask << 1 | 1) == sibling_bits {
+                write(directory, DIRECTORY_HEADER_SIZE + i as usize * size_of::<u64>(), &sibling_pid.x);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes `key`, returns its value.
+    pub fn remove(&self, key: K) -> Option<V> {
+        let hash = Self::hash(key);
+        let mut chain = BM::repeat(|| self.lock_chain(hash));
+        for page in &mut chain {
+            if let Some(i) = Self::find(bytes_of(&**page), key) {
+                return Some(Self::swap_remove(bytes_of_mut(&mut **page), i));
+            }
+        }
+        None
+    }
+}
+
+fn init_bucket(bucket: &mut [u8], local_depth: u32) {
+    write(bucket, COUNT_OFFSET, &0u16);
+    write(bucket, LOCAL_DEPTH_OFFSET, &(local_depth as u16));
+    write(bucket, NEXT_OFFSET, &NO_PAGE);
+}
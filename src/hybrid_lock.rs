@@ -0,0 +1,206 @@
+use crate::seqlock::{lock_track_check, PageLock, ParkWait, SeqLock, VersionFilter, WaitPolicy};
+use crate::{OlcVersion, OptimisticError};
+use bytemuck::Zeroable;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{fence, AtomicU64};
+use std::time::Instant;
+
+const EXCLUSIVE_MASK: u64 = 1;
+const VERSION_SHIFT: u32 = 1;
+
+/// A lock in the style of LeanStore's hybrid latch.
+///
+/// Optimistic readers only look at a version word, while pessimistic readers and writers coordinate through a
+/// separate reader-writer lock.
+/// Shared lockers therefore never write to the version word, so they do not slow down optimistic readers
+/// of the same page.
+/// Writers lock both, the version word only changes while the reader-writer lock is held exclusively.
+pub struct HybridLock<W: WaitPolicy = ParkWait> {
+    version: AtomicU64,
+    /// only used as a reader-writer lock, it is never unlocked in a way that increments its version
+    rw: SeqLock<W>,
+}
+
+unsafe impl<W: WaitPolicy> Zeroable for HybridLock<W> {}
+
+impl<W: WaitPolicy> Default for HybridLock<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: WaitPolicy> HybridLock<W> {
+    pub fn new() -> Self {
+        HybridLock { version: AtomicU64::new(0), rw: SeqLock::new() }
+    }
+
+    /// Applies `f` to the version of this lock instead of the version of `rw`.
+    fn filter<F: VersionFilter>(&self, f: F) -> OnVersion<'_, F> {
+        OnVersion { f, version: &self.version }
+    }
+
+    /// Checks `f` against the version once it can no longer change.
+    /// Releases the shared lock if the check fails.
+    fn check_shared<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        let x = self.version.load(Relaxed);
+        match f.check(x >> VERSION_SHIFT) {
+            Ok(()) => Ok(f.map_r(x >> VERSION_SHIFT)),
+            Err(e) => {
+                self.rw.unlock_shared();
+                Err(e)
+            }
+        }
+    }
+
+    fn set_exclusive<F: VersionFilter>(&self, f: F) -> F::R {
+        let x = self.version.fetch_or(EXCLUSIVE_MASK, Acquire);
+        debug_assert!(x & EXCLUSIVE_MASK == 0);
+        f.map_r(x >> VERSION_SHIFT)
+    }
+
+    /// clears the exclusive bit and increments the version, returns version after unlocking
+    fn clear_exclusive(&self) -> OlcVersion {
+        let fetched = self.version.fetch_add(EXCLUSIVE_MASK, Release);
+        debug_assert!(fetched & EXCLUSIVE_MASK != 0);
+        OlcVersion { x: fetched.wrapping_add(EXCLUSIVE_MASK) >> VERSION_SHIFT }
+    }
+}
+
+impl<W: WaitPolicy> PageLock for HybridLock<W> {
+    fn lock_shared<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        self.rw.lock_shared(self.filter(f))?;
+        self.check_shared(f)
+    }
+
+    fn try_lock_shared<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        self.rw.try_lock_shared(self.filter(f))?;
+        self.check_shared(f).ok()
+    }
+
+    fn unlock_shared(&self) -> OlcVersion {
+        let version = self.version.load(Relaxed) >> VERSION_SHIFT;
+        self.rw.unlock_shared();
+        OlcVersion { x: version }
+    }
+
+    fn lock_exclusive<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        // the reader-writer lock checks the filter after it got the lock, so the version is still valid
+        self.rw.lock_exclusive(self.filter(f))?;
+        Ok(self.set_exclusive(f))
+    }
+
+    fn try_lock_exclusive<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        self.rw.try_lock_exclusive(self.filter(f))?;
+        if f.check(self.version.load(Relaxed) >> VERSION_SHIFT).is_err() {
+            self.rw.unlock_exclusive_unmodified();
+            return None;
+        }
+        Some(self.set_exclusive(f))
+    }
+
+    fn force_lock_exclusive(&self) -> OlcVersion {
+        self.rw.force_lock_exclusive();
+        self.set_exclusive(())
+    }
+
+    fn unlock_exclusive(&self) -> OlcVersion {
+        let version = self.clear_exclusive();
+        self.rw.unlock_exclusive_unmodified();
+        version
+    }
+
+    fn unlock_exclusive_unmodified(&self) -> OlcVersion {
+        let fetched = self.version.fetch_and(!EXCLUSIVE_MASK, Release);
+        debug_assert!(fetched & EXCLUSIVE_MASK != 0);
+        self.rw.unlock_exclusive_unmodified();
+        OlcVersion { x: fetched >> VERSION_SHIFT }
+    }
+
+    fn try_upgrade_shared_to_exclusive(&self) -> Option<OlcVersion> {
+        self.rw.try_upgrade_shared_to_exclusive()?;
+        Some(self.set_exclusive(()))
+    }
+
+    fn downgrade_exclusive_to_shared(&self) -> OlcVersion {
+        let version = self.clear_exclusive();
+        self.rw.downgrade_exclusive_to_shared_unmodified();
+        version
+    }
+
+    fn lock_optimistic<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        lock_track_check(&self.rw, None);
+        loop {
+            let x = self.version.load(Acquire);
+            f.check(x >> VERSION_SHIFT)?;
+            if x & EXCLUSIVE_MASK == 0 {
+                return Ok(f.map_r(x >> VERSION_SHIFT));
+            }
+            // the version is only locked while the reader-writer lock is, so wait on that
+            self.rw.lock_optimistic(self.filter(f))?;
+        }
+    }
+
+    fn try_lock_optimistic<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        lock_track_check(&self.rw, None);
+        let x = self.version.load(Acquire);
+        f.check(x >> VERSION_SHIFT).ok()?;
+        if x & EXCLUSIVE_MASK == 0 {
+            Some(f.map_r(x >> VERSION_SHIFT))
+        } else {
+            None
+        }
+    }
+
+    fn try_unlock_optimistic(&self, v: OlcVersion) -> Result<(), OptimisticError> {
+        fence(Acquire);
+        let x = self.version.load(Relaxed);
+        if x & EXCLUSIVE_MASK == 0 && x >> VERSION_SHIFT == v.x {
+            Ok(())
+        } else {
+            Err(OptimisticError::new())
+        }
+    }
+}
+
+/// Checks the version word of a [`HybridLock`] while waiting for its reader-writer lock.
+#[derive(Clone, Copy)]
+struct OnVersion<'a, F> {
+    f: F,
+    version: &'a AtomicU64,
+}
+
+impl<F: VersionFilter> VersionFilter for OnVersion<'_, F> {
+    type E = F::E;
+    type R = ();
+
+    fn check(self, _v: u64) -> Result<(), Self::E> {
+        self.f.check(self.version.load(Relaxed) >> VERSION_SHIFT)
+    }
+
+    fn map_r(self, _v: u64) -> Self::R {}
+
+    fn deadline(self) -> Option<Instant> {
+        self.f.deadline()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rw_version_unchanged() {
+        let l = HybridLock::<ParkWait>::new();
+        let Ok(v) = l.lock_exclusive(());
+        assert!(l.unlock_exclusive() != v);
+        let Ok(_) = l.lock_exclusive(());
+        let v = l.downgrade_exclusive_to_shared();
+        assert!(l.unlock_shared() == v);
+        let Some(_) = l.try_lock_exclusive(()) else { panic!() };
+        l.unlock_exclusive_unmodified();
+        let Ok(rw) = l.rw.lock_optimistic(());
+        assert_eq!(rw.x, 0);
+        let Ok(v) = l.lock_optimistic(());
+        assert_eq!(v.x, 2);
+    }
+}
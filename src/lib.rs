@@ -9,15 +9,40 @@ use bytemuck::{Pod, Zeroable};
 pub use o_ptr::OPtr;
 pub use optimistic_error::{OlcErrorHandler, OptimisticError};
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
+mod btree;
 mod buffer_manager;
+mod dirty_set;
+mod disk_bm;
+mod free_list;
+mod hash_table;
+mod hybrid_lock;
+mod lock_guard;
+mod node;
 mod o_ptr;
+mod olc_cell;
 mod optimistic_error;
 mod seqlock;
+mod slotted_page;
+mod vmcache_bm;
+mod wal;
 
+pub use btree::BTree;
 pub use buffer_manager::*;
+pub use disk_bm::DiskBm;
+pub use hash_table::HashTable;
+pub use hybrid_lock::HybridLock;
+pub use lock_guard::{OptimisticRead, SeqLockGuard};
+pub use olc_cell::OlcCell;
 pub use optimistic_error::{PanicOlcEh, UnwindOlcEh};
+pub use seqlock::{Fair, LockTimeout, PageLock, ParkWait, SeqLock, SpinWait, VersionFilter, WaitPolicy, YieldWait};
+pub use slotted_page::{SlottedPageO, SlottedPageX};
+pub use vmcache_bm::VmCacheBm;
+pub use wal::{LogRecord, Lsn, Wal};
 
+/// The version of a page, incremented on every exclusive unlock.
+/// Versions wrap around, so they may only be compared for equality.
 #[derive(Eq, PartialEq, Clone, Copy)]
 pub struct OlcVersion {
     pub x: u64,
@@ -34,10 +59,23 @@ pub trait BufferManager<'bm>: 'bm + Copy + Send + Sync + Sized {
     type GuardO: OptimisticGuard<'bm, Self>
         + BufferManageGuardUpgrade<'bm, Self, Self::GuardS>
         + BufferManageGuardUpgrade<'bm, Self, Self::GuardX>;
-    type GuardS: BufferManagerGuard<'bm, Self> + Deref<Target = Self::Page>;
-    type GuardX: ExclusiveGuard<'bm, Self> + Deref<Target = Self::Page> + DerefMut;
+    type GuardS: BufferManagerGuard<'bm, Self>
+        + Deref<Target = Self::Page>
+        + BufferManageGuardDowngrade<'bm, Self, Self::GuardO>
+        + BufferManageGuardTryUpgrade<'bm, Self, Self::GuardX>;
+    type GuardX: ExclusiveGuard<'bm, Self>
+        + Deref<Target = Self::Page>
+        + DerefMut
+        + BufferManageGuardDowngrade<'bm, Self, Self::GuardS>
+        + BufferManageGuardDowngrade<'bm, Self, Self::GuardO>;
     type OlcEH: OlcErrorHandler;
-    fn alloc(self) -> Self::GuardX;
+    fn try_alloc(self) -> Result<Self::GuardX, AllocError>;
+    fn alloc(self) -> Self::GuardX {
+        match self.try_alloc() {
+            Ok(g) => g,
+            Err(e) => panic!("{e}"),
+        }
+    }
     #[deprecated]
     fn free(self, g: Self::GuardX) {
         g.dealloc();
@@ -62,13 +100,41 @@ pub trait BufferManagerExt<'bm>: BufferManager<'bm> {
     fn lock_exclusive(self, pid: PageId) -> Self::GuardX {
         Self::GuardX::acquire_wait(self, pid)
     }
+
+    fn try_lock_optimistic(self, pid: PageId) -> Option<Self::GuardO> {
+        Self::GuardO::try_acquire(self, pid)
+    }
+    fn try_lock_shared(self, pid: PageId) -> Option<Self::GuardS> {
+        Self::GuardS::try_acquire(self, pid)
+    }
+    fn try_lock_exclusive(self, pid: PageId) -> Option<Self::GuardX> {
+        Self::GuardX::try_acquire(self, pid)
+    }
+
+    fn lock_optimistic_timeout(self, pid: PageId, timeout: Duration) -> Result<Self::GuardO, LockTimeout> {
+        Self::GuardO::acquire_timeout(self, pid, timeout)
+    }
+    fn lock_shared_timeout(self, pid: PageId, timeout: Duration) -> Result<Self::GuardS, LockTimeout> {
+        Self::GuardS::acquire_timeout(self, pid, timeout)
+    }
+    fn lock_exclusive_timeout(self, pid: PageId, timeout: Duration) -> Result<Self::GuardX, LockTimeout> {
+        Self::GuardX::acquire_timeout(self, pid, timeout)
+    }
 }
 
 impl<'bm, BM: BufferManager<'bm>> BufferManagerExt<'bm> for BM {}
 
+/// Acquiring a guard on a page that is not resident loads it, which panics if reading the page fails.
 pub trait BufferManagerGuard<'bm, B: BufferManager<'bm>>: Sized {
     fn acquire_wait(bm: B, page_id: PageId) -> Self;
     fn acquire_wait_version(bm: B, page_id: PageId, v: OlcVersion) -> Option<Self>;
+    /// Like `acquire_wait`, but returns `None` instead of waiting if the page is locked,
+    /// or if it is not resident and another thread is loading or evicting it.
+    fn try_acquire(bm: B, page_id: PageId) -> Option<Self>;
+    /// Like `acquire_wait_version`, but returns `None` instead of waiting if the page is locked.
+    fn try_acquire_version(bm: B, page_id: PageId, v: OlcVersion) -> Option<Self>;
+    /// Like `acquire_wait`, but gives up once `timeout` has passed.
+    fn acquire_timeout(bm: B, page_id: PageId, timeout: Duration) -> Result<Self, LockTimeout>;
     fn release(self) -> OlcVersion;
     fn page_id(&self) -> PageId;
     fn o_ptr(&mut self) -> OPtr<'_, B::Page, B::OlcEH>;
@@ -85,6 +151,8 @@ pub trait OptimisticGuard<'bm, BM: BufferManager<'bm>>: BufferManagerGuard<'bm,
 }
 
 pub trait ExclusiveGuard<'bm, BM: BufferManager<'bm>>: BufferManagerGuard<'bm, BM> {
+    /// Forgets that the page was written through this guard, as if it had not been.
+    /// The page is then not marked dirty on release, so its contents must have been restored.
     fn reset_written(&mut self);
     fn dealloc(self);
 }
@@ -92,3 +160,18 @@ pub trait ExclusiveGuard<'bm, BM: BufferManager<'bm>>: BufferManagerGuard<'bm, B
 pub trait BufferManageGuardUpgrade<'bm, B: BufferManager<'bm>, Target>: Sized {
     fn upgrade(self) -> Target;
 }
+
+/// Converts a guard into a weaker one without releasing the page in between.
+/// An optimistic guard obtained this way validates against the version after any writes made under the old guard.
+pub trait BufferManageGuardDowngrade<'bm, B: BufferManager<'bm>, Target>: Sized {
+    fn downgrade(self) -> Target;
+}
+
+/// Upgrades a shared guard to an exclusive one.
+pub trait BufferManageGuardTryUpgrade<'bm, B: BufferManager<'bm>, Target>: Sized {
+    /// Succeeds atomically if the caller is the only reader, otherwise returns the unchanged guard.
+    fn try_upgrade(self) -> Result<Target, Self>;
+    /// Like `try_upgrade`, but if there are other readers, releases the page and waits for an exclusive lock.
+    /// Returns `None` if the page was modified in between.
+    fn upgrade_validated(self) -> Option<Target>;
+}
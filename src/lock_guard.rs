@@ -0,0 +1,121 @@
+use crate::seqlock::{PageLock, SeqLock};
+use crate::{OlcVersion, OptimisticError};
+use std::mem::forget;
+
+/// Holds a lock in shared or exclusive mode and releases it on drop.
+///
+/// This only manages the lock, the protected data is accessed separately.
+pub struct SeqLockGuard<'a, L: PageLock = SeqLock> {
+    lock: &'a L,
+    exclusive: bool,
+}
+
+impl<'a, L: PageLock> SeqLockGuard<'a, L> {
+    pub fn shared(lock: &'a L) -> Self {
+        let Ok(_) = lock.lock_shared(());
+        SeqLockGuard { lock, exclusive: false }
+    }
+
+    pub fn exclusive(lock: &'a L) -> Self {
+        let Ok(_) = lock.lock_exclusive(());
+        SeqLockGuard { lock, exclusive: true }
+    }
+
+    pub fn try_shared(lock: &'a L) -> Option<Self> {
+        lock.try_lock_shared(())?;
+        Some(SeqLockGuard { lock, exclusive: false })
+    }
+
+    pub fn try_exclusive(lock: &'a L) -> Option<Self> {
+        lock.try_lock_exclusive(())?;
+        Some(SeqLockGuard { lock, exclusive: true })
+    }
+
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    /// Turns an exclusive guard into a shared one without letting a writer in between.
+    pub fn downgrade(&mut self) {
+        if self.exclusive {
+            self.lock.downgrade_exclusive_to_shared();
+            self.exclusive = false;
+        }
+    }
+
+    /// Turns a shared guard into an exclusive one if this is the only reader.
+    pub fn try_upgrade(&mut self) -> bool {
+        if !self.exclusive {
+            self.exclusive = self.lock.try_upgrade_shared_to_exclusive().is_some();
+        }
+        self.exclusive
+    }
+
+    /// returns version after unlocking
+    pub fn release(self) -> OlcVersion {
+        let version = if self.exclusive { self.lock.unlock_exclusive() } else { self.lock.unlock_shared() };
+        forget(self);
+        version
+    }
+}
+
+impl<L: PageLock> Drop for SeqLockGuard<'_, L> {
+    fn drop(&mut self) {
+        if self.exclusive {
+            self.lock.unlock_exclusive();
+        } else {
+            self.lock.unlock_shared();
+        }
+    }
+}
+
+/// An optimistic read of data protected by a lock.
+///
+/// Reads are only consistent if [`check`](Self::check) succeeds afterwards.
+/// Unlike the optimistic guards of buffer managers, dropping this does not validate anything.
+pub struct OptimisticRead<'a, L: PageLock = SeqLock> {
+    lock: &'a L,
+    version: OlcVersion,
+}
+
+impl<L: PageLock> Clone for OptimisticRead<'_, L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<L: PageLock> Copy for OptimisticRead<'_, L> {}
+
+impl<'a, L: PageLock> OptimisticRead<'a, L> {
+    /// Waits until the lock is not held exclusively.
+    pub fn new(lock: &'a L) -> Self {
+        let Ok(version) = lock.lock_optimistic(());
+        OptimisticRead { lock, version }
+    }
+
+    pub fn try_new(lock: &'a L) -> Option<Self> {
+        let version = lock.try_lock_optimistic(())?;
+        Some(OptimisticRead { lock, version })
+    }
+
+    pub fn version(&self) -> OlcVersion {
+        self.version
+    }
+
+    /// Fails if the lock was locked exclusively since this read started.
+    pub fn check(&self) -> Result<(), OptimisticError> {
+        self.lock.try_unlock_optimistic(self.version)
+    }
+
+    /// Acquires a shared lock, fails if the data changed since this read started.
+    pub fn upgrade_shared(self) -> Result<SeqLockGuard<'a, L>, OptimisticError> {
+        self.lock.lock_shared(self.version)?;
+        Ok(SeqLockGuard { lock: self.lock, exclusive: false })
+    }
+
+    /// Acquires an exclusive lock, fails if the data changed since this read started.
+    pub fn upgrade_exclusive(self) -> Result<SeqLockGuard<'a, L>, OptimisticError> {
+        self.lock.lock_exclusive(self.version)?;
+        Ok(SeqLockGuard { lock: self.lock, exclusive: true })
+    }
+}
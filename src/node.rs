@@ -0,0 +1,25 @@
+use crate::{OPtr, OlcErrorHandler};
+use bytemuck::{bytes_of, bytes_of_mut, pod_read_unaligned, Pod};
+
+pub(crate) fn write<T: Pod>(bytes: &mut [u8], offset: usize, x: &T) {
+    bytes[offset..offset + size_of::<T>()].copy_from_slice(bytes_of(x));
+}
+
+/// Node contents are read optimistically during traversal and directly under an exclusive lock.
+pub(crate) trait NodeRead: Copy {
+    fn read<T: Pod>(self, offset: usize) -> T;
+}
+
+impl NodeRead for &[u8] {
+    fn read<T: Pod>(self, offset: usize) -> T {
+        pod_read_unaligned(&self[offset..offset + size_of::<T>()])
+    }
+}
+
+impl<O: OlcErrorHandler> NodeRead for OPtr<'_, [u8], O> {
+    fn read<T: Pod>(self, offset: usize) -> T {
+        let mut x = T::zeroed();
+        self.sub(offset, size_of::<T>()).load_bytes(bytes_of_mut(&mut x));
+        x
+    }
+}
@@ -0,0 +1,67 @@
+use crate::lock_guard::SeqLockGuard;
+use crate::seqlock::{PageLock, SeqLock};
+use crate::{OPtr, OlcErrorHandler, UnwindOlcEh};
+use bytemuck::Pod;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+
+/// A small value that many threads read optimistically and that is occasionally replaced.
+///
+/// Readers never write to shared memory, so reads scale with the number of threads.
+pub struct OlcCell<T: Pod, O: OlcErrorHandler = UnwindOlcEh, L: PageLock = SeqLock> {
+    lock: L,
+    value: UnsafeCell<T>,
+    _o: PhantomData<O>,
+}
+
+unsafe impl<T: Pod, O: OlcErrorHandler, L: PageLock> Sync for OlcCell<T, O, L> {}
+unsafe impl<T: Pod, O: OlcErrorHandler, L: PageLock> Send for OlcCell<T, O, L> {}
+
+impl<T: Pod + Default, O: OlcErrorHandler, L: PageLock> Default for OlcCell<T, O, L> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Pod, O: OlcErrorHandler, L: PageLock> OlcCell<T, O, L> {
+    pub fn new(value: T) -> Self {
+        OlcCell { lock: L::zeroed(), value: UnsafeCell::new(value), _o: PhantomData }
+    }
+
+    /// Runs `f` on the value optimistically, retrying until it observed a consistent value.
+    /// `f` may see torn values, so it must not have side effects and must not rely on the value for its control flow.
+    /// It may raise an optimistic failure through `O` when it notices a torn value, `f` is retried then as well.
+    /// Validation does not go through `O`, so this works with any error handler.
+    pub fn read<R>(&self, mut f: impl FnMut(OPtr<'_, T, O>) -> R) -> R {
+        loop {
+            let Ok(version) = self.lock.lock_optimistic(());
+            let r = O::catch(|| f(unsafe { OPtr::from_raw(self.value.get()) }));
+            if let (Ok(r), Ok(())) = (r, self.lock.try_unlock_optimistic(version)) {
+                return r;
+            }
+        }
+    }
+
+    /// Returns a copy of the value.
+    pub fn load(&self) -> T {
+        self.read(|p| unsafe { std::ptr::read_volatile(p.to_raw()) })
+    }
+
+    /// Runs `f` on the value under an exclusive lock.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _guard = SeqLockGuard::exclusive(&self.lock);
+        f(unsafe { &mut *self.value.get() })
+    }
+
+    pub fn store(&self, value: T) {
+        self.write(|v| *v = value)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
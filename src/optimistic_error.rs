@@ -72,8 +72,9 @@ impl OlcErrorHandler for PanicOlcEh {
         panic!("{e}")
     }
 
-    fn catch<R>(_f: impl FnOnce() -> R) -> Result<R, OptimisticError> {
-        unimplemented!()
+    /// Failures panic instead of unwinding with an [`OptimisticError`], so there is nothing to catch.
+    fn catch<R>(f: impl FnOnce() -> R) -> Result<R, OptimisticError> {
+        Ok(f())
     }
 
     fn is_unwinding() -> bool {
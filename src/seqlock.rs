@@ -1,21 +1,145 @@
 use crate::{OlcVersion, OptimisticError};
 use bytemuck::Zeroable;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use std::sync::atomic::{fence, AtomicU64};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-#[derive(Zeroable)]
-pub struct SeqLock(AtomicU64);
+/// A lock word holding a reader count, some flags and a version that is incremented by every exclusive unlock.
+///
+/// Buffer managers use it to lock pages, but it can protect any data.
+/// [`SeqLockGuard`](crate::SeqLockGuard) and [`OptimisticRead`](crate::OptimisticRead) release it automatically.
+///
+/// The reader count is `COUNT_BITS` wide, which must be between 2 and 21.
+/// Once it saturates, further readers are counted in a global side table, which is slower but never blocks them.
+///
+/// The version has [`Self::VERSION_BITS`] bits and wraps around to zero after `2^VERSION_BITS` exclusive unlocks.
+/// Versions are only compared for equality, so wrap-around is harmless unless an optimistic reader validates
+/// after exactly a multiple of `2^VERSION_BITS` writes to the same page.
+/// With the default layout, that takes about 26 days per page at one write per nanosecond.
+/// The bound on `COUNT_BITS` keeps the version at least 40 bits wide, which still takes 18 minutes.
+pub struct SeqLock<W: WaitPolicy = ParkWait, const COUNT_BITS: u32 = 10>(AtomicU64, PhantomData<fn() -> W>);
 
-const COUNT_BITS: u32 = 10;
-const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
-const EXCLUSIVE_MASK: u64 = 1 << COUNT_BITS;
-const VERSION_SHIFT: u32 = COUNT_BITS + 1;
+unsafe impl<W: WaitPolicy, const COUNT_BITS: u32> Zeroable for SeqLock<W, COUNT_BITS> {}
 
+/// Readers beyond the capacity of the reader count, keyed by lock address.
+/// Only accessed while the count of the lock is saturated, entries only exist while it is.
+static SPILLED_READERS: Mutex<BTreeMap<usize, u64>> = Mutex::new(BTreeMap::new());
+
+/// Writers queued for a lock in fair mode, keyed by lock address.
+/// An entry exists exactly while the handoff bit of its lock is set, both only change while holding the table.
+static WRITER_QUEUES: Mutex<BTreeMap<usize, Tickets>> = Mutex::new(BTreeMap::new());
+
+/// The ticket queue of one lock, writers get the lock in the order they drew their tickets.
+struct Tickets {
+    next: u64,
+    serving: u64,
+    /// tickets of writers that gave up before they were served
+    abandoned: BTreeSet<u64>,
+}
+
+/// rounds a writer waits in fair mode before queueing for the lock
+const QUEUE_ROUNDS: u32 = 4;
+
+/// Decides how a thread waits for a [`SeqLock`] that is held in a conflicting mode.
+pub trait WaitPolicy: 'static {
+    /// In fair mode, a writer that keeps losing the race for the lock draws a ticket, and queued writers get the
+    /// lock in ticket order. While writers are queued, new shared lockers stand back and other writers queue too.
+    /// A queued writer is only overtaken by the writers ahead of it and those that were already acquiring the lock
+    /// when it queued. Readers can starve while writers keep queueing.
+    const FAIR: bool = false;
+
+    /// Called each time an acquisition finds the lock unavailable, `round` counts the calls within one acquisition.
+    /// Either waits briefly and returns `false`, or returns `true` to park the thread until the lock word changes.
+    fn pause(round: u32) -> bool;
+}
+
+/// Busy waits with exponential backoff, for short critical sections on latency critical paths.
+pub struct SpinWait;
+
+/// Yields to the scheduler, mostly useful for tests.
+pub struct YieldWait;
+
+/// Spins, then yields, then parks on a futex.
+/// This behaves well when there are more threads than cores.
+pub struct ParkWait;
+
+/// Enables fair mode on top of the wait policy `W`, see [`WaitPolicy::FAIR`].
+pub struct Fair<W>(PhantomData<W>);
+
+/// rounds of exponential spinning before yielding or capping the backoff
+const SPIN_ROUNDS: u32 = 6;
+/// rounds of yielding before parking
+const YIELD_ROUNDS: u32 = 4;
+
+fn spin(round: u32) {
+    for _ in 0..1 << round.min(SPIN_ROUNDS) {
+        std::hint::spin_loop();
+    }
+}
+
+impl WaitPolicy for SpinWait {
+    fn pause(round: u32) -> bool {
+        spin(round);
+        false
+    }
+}
+
+impl WaitPolicy for YieldWait {
+    fn pause(_round: u32) -> bool {
+        std::thread::yield_now();
+        false
+    }
+}
+
+impl<W: WaitPolicy> WaitPolicy for Fair<W> {
+    const FAIR: bool = true;
+
+    fn pause(round: u32) -> bool {
+        W::pause(round)
+    }
+}
+
+impl WaitPolicy for ParkWait {
+    fn pause(round: u32) -> bool {
+        if round < SPIN_ROUNDS {
+            spin(round);
+            false
+        } else if round < SPIN_ROUNDS + YIELD_ROUNDS {
+            std::thread::yield_now();
+            false
+        } else {
+            true
+        }
+    }
+}
+
+/// Returned when a lock could not be acquired before the deadline.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LockTimeout;
+
+impl Display for LockTimeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("lock acquisition timed out")
+    }
+}
+
+/// Decides which versions a lock acquisition accepts and what it returns on success.
 pub trait VersionFilter: Copy {
     type E;
     type R;
+    /// Called with the current version while acquiring, the acquisition fails if this does.
     fn check(self, v: u64) -> Result<(), Self::E>;
+    /// Produces the result of a successful acquisition from the version at that point.
     fn map_r(self, v: u64) -> Self::R;
+    /// Parked threads wake up at this point so `check` can fail.
+    fn deadline(self) -> Option<Instant> {
+        None
+    }
 }
 
 impl VersionFilter for () {
@@ -47,113 +171,544 @@ impl VersionFilter for OlcVersion {
     }
 }
 
-impl Default for SeqLock {
+/// Gives up once the deadline has passed.
+impl VersionFilter for Instant {
+    type E = LockTimeout;
+    type R = OlcVersion;
+
+    fn check(self, _v: u64) -> Result<(), Self::E> {
+        if Instant::now() < self {
+            Ok(())
+        } else {
+            Err(LockTimeout)
+        }
+    }
+
+    fn map_r(self, v: u64) -> Self::R {
+        OlcVersion { x: v }
+    }
+
+    fn deadline(self) -> Option<Instant> {
+        Some(self)
+    }
+}
+
+/// The lock of a page, used by the guards of [`CommonSeqLockBM`](crate::CommonSeqLockBM) buffer managers.
+///
+/// Implemented by [`SeqLock`] and [`HybridLock`](crate::HybridLock).
+/// Other implementations, e.g. ones that record statistics, can wrap these.
+/// A zeroed lock must be unlocked.
+///
+/// The lock has a version, which is incremented whenever an exclusive lock is released.
+/// Acquisition methods take a [`VersionFilter`] that is checked against the version:
+/// `()` accepts any version and returns it, an [`OlcVersion`] only accepts that exact version.
+/// Methods that return a version after unlocking return the version a subsequent optimistic lock would observe.
+pub trait PageLock: Zeroable + Sync + Send + 'static {
+    /// Waits until no thread holds the lock exclusively, then adds a reader.
+    fn lock_shared<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E>;
+    /// Like `lock_shared`, but returns `None` instead of waiting or if the filter rejects the version.
+    fn try_lock_shared<F: VersionFilter>(&self, f: F) -> Option<F::R>;
+    /// Like `lock_shared`, but gives up after `timeout`.
+    /// The lock is always tried at least once, even if `timeout` is zero.
+    fn lock_shared_timeout(&self, timeout: Duration) -> Result<OlcVersion, LockTimeout> {
+        match self.try_lock_shared(()) {
+            Some(v) => Ok(v),
+            None => self.lock_shared(Instant::now() + timeout),
+        }
+    }
+    fn unlock_shared(&self) -> OlcVersion;
+    /// Waits until no other thread holds the lock, returns the version before locking.
+    fn lock_exclusive<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E>;
+    /// Like `lock_exclusive`, but returns `None` instead of waiting or if the filter rejects the version.
+    fn try_lock_exclusive<F: VersionFilter>(&self, f: F) -> Option<F::R>;
+    /// Like `lock_exclusive`, but gives up after `timeout`.
+    /// The lock is always tried at least once, even if `timeout` is zero.
+    fn lock_exclusive_timeout(&self, timeout: Duration) -> Result<OlcVersion, LockTimeout> {
+        match self.try_lock_exclusive(()) {
+            Some(v) => Ok(v),
+            None => self.lock_exclusive(Instant::now() + timeout),
+        }
+    }
+    /// Locks exclusively without waiting, the caller must know that no other thread holds the lock.
+    fn force_lock_exclusive(&self) -> OlcVersion;
+    /// Releases the exclusive lock and increments the version.
+    fn unlock_exclusive(&self) -> OlcVersion;
+    /// Releases the exclusive lock without incrementing the version, the caller must not have modified the page.
+    /// Buffer managers use this after inspecting a page they decided not to evict.
+    /// The default increments the version anyway, which is correct but invalidates optimistic readers.
+    fn unlock_exclusive_unmodified(&self) -> OlcVersion {
+        self.unlock_exclusive()
+    }
+    /// Atomically turns a shared lock into an exclusive lock if the caller is the only reader.
+    fn try_upgrade_shared_to_exclusive(&self) -> Option<OlcVersion>;
+    /// Atomically turns an exclusive lock into a shared lock and increments the version.
+    fn downgrade_exclusive_to_shared(&self) -> OlcVersion;
+    /// Waits until no thread holds the lock exclusively and returns the version to validate against.
+    fn lock_optimistic<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E>;
+    /// Like `lock_optimistic`, but returns `None` instead of waiting or if the filter rejects the version.
+    fn try_lock_optimistic<F: VersionFilter>(&self, f: F) -> Option<F::R>;
+    /// Like `lock_optimistic`, but gives up after `timeout`.
+    /// The lock is always tried at least once, even if `timeout` is zero.
+    fn lock_optimistic_timeout(&self, timeout: Duration) -> Result<OlcVersion, LockTimeout> {
+        match self.try_lock_optimistic(()) {
+            Some(v) => Ok(v),
+            None => self.lock_optimistic(Instant::now() + timeout),
+        }
+    }
+    /// Fails if the lock was locked exclusively since `lock_optimistic` returned `v`.
+    fn try_unlock_optimistic(&self, v: OlcVersion) -> Result<(), OptimisticError>;
+}
+
+impl<W: WaitPolicy, const COUNT_BITS: u32> PageLock for SeqLock<W, COUNT_BITS> {
+    fn lock_shared<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        self.lock_shared(f)
+    }
+    fn try_lock_shared<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        self.try_lock_shared(f)
+    }
+    fn unlock_shared(&self) -> OlcVersion {
+        self.unlock_shared()
+    }
+    fn lock_exclusive<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        self.lock_exclusive(f)
+    }
+    fn try_lock_exclusive<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        self.try_lock_exclusive(f)
+    }
+    fn force_lock_exclusive(&self) -> OlcVersion {
+        self.force_lock_exclusive()
+    }
+    fn unlock_exclusive(&self) -> OlcVersion {
+        self.unlock_exclusive()
+    }
+    fn unlock_exclusive_unmodified(&self) -> OlcVersion {
+        self.unlock_exclusive_unmodified()
+    }
+    fn try_upgrade_shared_to_exclusive(&self) -> Option<OlcVersion> {
+        self.try_upgrade_shared_to_exclusive()
+    }
+    fn downgrade_exclusive_to_shared(&self) -> OlcVersion {
+        self.downgrade_exclusive_to_shared()
+    }
+    fn lock_optimistic<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
+        self.lock_optimistic(f)
+    }
+    fn try_lock_optimistic<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        self.try_lock_optimistic(f)
+    }
+    fn try_unlock_optimistic(&self, v: OlcVersion) -> Result<(), OptimisticError> {
+        self.try_unlock_optimistic(v)
+    }
+}
+
+impl<W: WaitPolicy, const COUNT_BITS: u32> Default for SeqLock<W, COUNT_BITS> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl SeqLock {
+impl<W: WaitPolicy, const COUNT_BITS: u32> SeqLock<W, COUNT_BITS> {
+    const COUNT_MASK: u64 = (1 << COUNT_BITS) - 1;
+    /// set while some thread is parked on the lock word.
+    /// It sits below the exclusive bit, so that the carry of unlock_exclusive does not touch it.
+    const WAITING_MASK: u64 = 1 << COUNT_BITS;
+    /// set while writers are queued for the lock in [`WRITER_QUEUES`], only used by fair wait policies.
+    const HANDOFF_MASK: u64 = 1 << (COUNT_BITS + 1);
+    const EXCLUSIVE_MASK: u64 = 1 << (COUNT_BITS + 2);
+    const VERSION_SHIFT: u32 = COUNT_BITS + 3;
+    /// width of the version stored in the lock word
+    pub const VERSION_BITS: u32 = u64::BITS - Self::VERSION_SHIFT;
+    /// a single reader must not saturate the count, the flags must fit in the futex word
+    /// and the version must be too wide to wrap during an optimistic read
+    const VALID_LAYOUT: () = assert!(COUNT_BITS >= 2 && Self::VERSION_SHIFT <= 32 && Self::VERSION_BITS >= 40);
+
+    /// Creates an unlocked lock with version 0.
     pub fn new() -> Self {
-        SeqLock(AtomicU64::new(0))
+        SeqLock(AtomicU64::new(0), PhantomData)
     }
+
+    /// Waits until no thread holds the lock exclusively, then adds a reader.
+    /// returns version
     pub fn lock_shared<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
         lock_track_check(self, Some(false));
+        let mut round = 0;
         let mut x = self.0.load(Relaxed);
         loop {
-            f.check(x >> VERSION_SHIFT)?;
-            if x & (COUNT_MASK | EXCLUSIVE_MASK) < COUNT_MASK {
+            f.check(x >> Self::VERSION_SHIFT)?;
+            if x & (Self::COUNT_MASK | Self::HANDOFF_MASK | Self::EXCLUSIVE_MASK) < Self::COUNT_MASK {
                 match self.0.compare_exchange_weak(x, x + 1, Acquire, Relaxed) {
                     Ok(_) => {
                         lock_track_set(self, Some(false));
-                        return Ok(f.map_r(x >> VERSION_SHIFT));
+                        return Ok(f.map_r(x >> Self::VERSION_SHIFT));
+                    }
+                    Err(v) => x = v,
+                }
+            } else if x & (Self::HANDOFF_MASK | Self::EXCLUSIVE_MASK) == 0 {
+                match self.spill_lock_shared(x) {
+                    Ok(()) => {
+                        lock_track_set(self, Some(false));
+                        return Ok(f.map_r(x >> Self::VERSION_SHIFT));
                     }
                     Err(v) => x = v,
                 }
             } else {
-                self.wait();
+                self.wait(x, &mut round, f.deadline());
+                x = self.0.load(Relaxed);
             }
         }
     }
 
+    /// Like `lock_shared`, but returns `None` instead of waiting.
+    /// Also returns `None` if the version check fails.
+    pub fn try_lock_shared<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        lock_track_check(self, Some(false));
+        let mut x = self.0.load(Relaxed);
+        loop {
+            f.check(x >> Self::VERSION_SHIFT).ok()?;
+            if x & (Self::HANDOFF_MASK | Self::EXCLUSIVE_MASK) != 0 {
+                return None;
+            }
+            let locked = if x & Self::COUNT_MASK == Self::COUNT_MASK {
+                self.spill_lock_shared(x)
+            } else {
+                self.0.compare_exchange_weak(x, x + 1, Acquire, Relaxed).map(|_| ())
+            };
+            match locked {
+                Ok(()) => {
+                    lock_track_set(self, Some(false));
+                    return Some(f.map_r(x >> Self::VERSION_SHIFT));
+                }
+                Err(v) => x = v,
+            }
+        }
+    }
+
+    /// Adds a reader to the side table, `x` is the expected lock word with a saturated count.
+    fn spill_lock_shared(&self, x: u64) -> Result<(), u64> {
+        let mut spilled = SPILLED_READERS.lock().unwrap();
+        // other readers only leave a saturated count while holding the table, so the count stays saturated
+        self.0.compare_exchange(x, x, Acquire, Relaxed)?;
+        *spilled.entry(self.addr()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// returns version, which is unchanged by shared locking
     pub fn unlock_shared(&self) -> OlcVersion {
         lock_track_set(self, None);
-        let fetched = self.0.fetch_sub(1, Release);
-        debug_assert!(fetched & COUNT_MASK != 0);
-        OlcVersion { x: fetched >> VERSION_SHIFT }
+        let mut x = self.0.load(Relaxed);
+        loop {
+            debug_assert!(x & Self::COUNT_MASK != 0);
+            if x & Self::COUNT_MASK == Self::COUNT_MASK {
+                let mut spilled = SPILLED_READERS.lock().unwrap();
+                if let Entry::Occupied(mut e) = spilled.entry(self.addr()) {
+                    *e.get_mut() -= 1;
+                    if *e.get() == 0 {
+                        e.remove();
+                    }
+                    // a spilled reader left, the lock word stays the same
+                    return OlcVersion { x: self.0.load(Relaxed) >> Self::VERSION_SHIFT };
+                }
+                let fetched = self.0.fetch_sub(1, Release);
+                drop(spilled);
+                self.wake(fetched);
+                return OlcVersion { x: fetched >> Self::VERSION_SHIFT };
+            }
+            match self.0.compare_exchange_weak(x, x - 1, Release, Relaxed) {
+                Ok(_) => {
+                    self.wake(x);
+                    return OlcVersion { x: x >> Self::VERSION_SHIFT };
+                }
+                Err(v) => x = v,
+            }
+        }
     }
 
-    fn wait(&self) {
-        //TODO
-        std::thread::yield_now();
+    fn addr(&self) -> usize {
+        (self as *const Self).addr()
+    }
+
+    /// Waits for the lock word to change from `observed` as decided by the wait policy.
+    /// May return early, callers must reload the lock word and check again.
+    /// Parked threads wake up at `deadline`.
+    fn wait(&self, observed: u64, round: &mut u32, deadline: Option<Instant>) {
+        self.wait_until(observed, round, deadline, || false);
     }
 
+    /// Like `wait`, but does not park if `ready` returns true.
+    /// `ready` is checked after announcing the parked thread, so a thread that makes it true and then calls `wake`
+    /// cannot be missed.
+    fn wait_until(&self, observed: u64, round: &mut u32, deadline: Option<Instant>, ready: impl FnOnce() -> bool) {
+        let () = Self::VALID_LAYOUT;
+        let park = W::pause(*round);
+        *round = round.saturating_add(1);
+        if park {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => return,
+                },
+                None => None,
+            };
+            if observed & Self::WAITING_MASK == 0
+                && self.0.compare_exchange(observed, observed | Self::WAITING_MASK, Relaxed, Relaxed).is_err()
+            {
+                return;
+            }
+            if ready() {
+                return;
+            }
+            futex_wait(&self.0, observed | Self::WAITING_MASK, timeout);
+        }
+    }
+
+    /// Wakes parked threads if `fetched`, the value before releasing the lock, indicates there are any.
+    fn wake(&self, fetched: u64) {
+        let () = Self::VALID_LAYOUT;
+        if fetched & Self::WAITING_MASK != 0 {
+            self.0.fetch_and(!Self::WAITING_MASK, Relaxed);
+            futex_wake(&self.0);
+        }
+    }
+
+    /// Waits until no other thread holds the lock.
     /// returns version before locking
     pub fn lock_exclusive<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
         lock_track_check(self, Some(true));
+        let mut round = 0;
+        // the ticket of this writer once it queued in fair mode, and whether it is being served
+        let mut ticket = None;
+        let mut served = false;
         loop {
             let mut x = self.0.load(Relaxed);
-            f.check(x >> VERSION_SHIFT)?;
-            if x & EXCLUSIVE_MASK == 0 {
-                x = self.0.fetch_or(EXCLUSIVE_MASK, Acquire);
-                if x & EXCLUSIVE_MASK != 0 {
-                    self.wait();
+            if let Err(e) = f.check(x >> Self::VERSION_SHIFT) {
+                if let Some(ticket) = ticket {
+                    let fetched = self.leave_queue(ticket);
+                    self.wake(fetched);
+                }
+                return Err(e);
+            }
+            served = served || ticket.is_some_and(|ticket| self.is_served(ticket));
+            if x & Self::EXCLUSIVE_MASK != 0 || (x & Self::HANDOFF_MASK != 0 && !served) {
+                if W::FAIR && ticket.is_none() && (x & Self::HANDOFF_MASK != 0 || round >= QUEUE_ROUNDS) {
+                    ticket = Some(self.take_ticket());
+                } else if let Some(ticket) = ticket.filter(|_| !served) {
+                    self.wait_until(x, &mut round, f.deadline(), || self.is_served(ticket));
+                } else {
+                    self.wait(x, &mut round, f.deadline());
+                }
+                continue;
+            }
+            if let Some(t) = ticket {
+                // writers that loaded the lock word before it was reserved may still race for it
+                if self.0.compare_exchange(x, x | Self::EXCLUSIVE_MASK, Acquire, Relaxed).is_err() {
                     continue;
                 }
-                if f.check(x >> VERSION_SHIFT).is_err() {
-                    self.0.fetch_and(!EXCLUSIVE_MASK, Relaxed);
-                    self.wait();
+                self.leave_queue(t);
+                ticket = None;
+            } else {
+                x = self.0.fetch_or(Self::EXCLUSIVE_MASK, Acquire);
+                if x & Self::EXCLUSIVE_MASK != 0 {
+                    self.wait(x, &mut round, f.deadline());
                     continue;
                 }
-                if x & (EXCLUSIVE_MASK | COUNT_MASK) == 0 {
+            }
+            if f.check(x >> Self::VERSION_SHIFT).is_err() {
+                let fetched = self.0.fetch_and(!Self::EXCLUSIVE_MASK, Relaxed);
+                self.wake(fetched);
+                // the version cannot change back, so the next check fails
+                continue;
+            }
+            if x & Self::COUNT_MASK == 0 {
+                lock_track_set(self, Some(true));
+                return Ok(f.map_r(x >> Self::VERSION_SHIFT));
+            }
+            x |= Self::EXCLUSIVE_MASK;
+            loop {
+                self.wait(x, &mut round, f.deadline());
+                x = self.0.load(Acquire);
+                if x & Self::COUNT_MASK == 0 {
                     lock_track_set(self, Some(true));
-                    return Ok(f.map_r(x >> VERSION_SHIFT));
+                    return Ok(f.map_r(x >> Self::VERSION_SHIFT));
                 }
-                loop {
-                    self.wait();
-                    x = self.0.load(Acquire);
-                    if x & COUNT_MASK == 0 {
-                        lock_track_set(self, Some(true));
-                        return Ok(f.map_r(x >> VERSION_SHIFT));
-                    }
+                // readers are still draining, nothing was written yet so the version stays the same
+                if let Err(e) = f.check(x >> Self::VERSION_SHIFT) {
+                    let fetched = self.0.fetch_and(!Self::EXCLUSIVE_MASK, Relaxed);
+                    self.wake(fetched);
+                    return Err(e);
                 }
             }
         }
     }
 
+    /// Queues the calling writer, returns its ticket.
+    /// The first writer in the queue sets the handoff bit, which keeps the lock reserved until the queue is empty.
+    fn take_ticket(&self) -> u64 {
+        let mut queues = WRITER_QUEUES.lock().unwrap();
+        let tickets = queues.entry(self.addr()).or_insert_with(|| {
+            self.0.fetch_or(Self::HANDOFF_MASK, Relaxed);
+            Tickets { next: 0, serving: 0, abandoned: BTreeSet::new() }
+        });
+        tickets.next += 1;
+        tickets.next - 1
+    }
+
+    fn is_served(&self, ticket: u64) -> bool {
+        WRITER_QUEUES.lock().unwrap()[&self.addr()].serving == ticket
+    }
+
+    /// Removes `ticket` from the queue, the served writer calls this once it holds the lock, others when they give up.
+    /// The next writer is served, or the reservation is released if the queue is empty.
+    /// returns the lock word to pass to `wake`
+    fn leave_queue(&self, ticket: u64) -> u64 {
+        let mut queues = WRITER_QUEUES.lock().unwrap();
+        let Entry::Occupied(mut entry) = queues.entry(self.addr()) else { unreachable!() };
+        let tickets = entry.get_mut();
+        if tickets.serving != ticket {
+            tickets.abandoned.insert(ticket);
+            return self.0.load(Relaxed);
+        }
+        tickets.serving += 1;
+        while tickets.abandoned.remove(&tickets.serving) {
+            tickets.serving += 1;
+        }
+        if tickets.serving == tickets.next {
+            entry.remove();
+            self.0.fetch_and(!Self::HANDOFF_MASK, Relaxed)
+        } else {
+            self.0.load(Relaxed)
+        }
+    }
+
+    /// Like `lock_exclusive`, but returns `None` instead of waiting if the lock is held or reserved.
+    /// Also returns `None` if the version check fails.
+    /// Buffer managers use this to probe pages the current thread may hold, so held locks are not tracked here.
+    pub fn try_lock_exclusive<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        let mut x = self.0.load(Relaxed);
+        loop {
+            f.check(x >> Self::VERSION_SHIFT).ok()?;
+            if x & (Self::EXCLUSIVE_MASK | Self::HANDOFF_MASK | Self::COUNT_MASK) != 0 {
+                return None;
+            }
+            match self.0.compare_exchange_weak(x, x | Self::EXCLUSIVE_MASK, Acquire, Relaxed) {
+                Ok(_) => {
+                    lock_track_set(self, Some(true));
+                    return Some(f.map_r(x >> Self::VERSION_SHIFT));
+                }
+                Err(v) => x = v,
+            }
+        }
+    }
+
+    /// Locks exclusively without waiting, the caller must know that no other thread holds the lock,
+    /// e.g. because it just allocated the protected object.
+    /// returns version before locking
     pub fn force_lock_exclusive(&self) -> OlcVersion {
         lock_track_check(self, Some(true));
         lock_track_set(self, Some(true));
-        let x = self.0.fetch_or(EXCLUSIVE_MASK, Acquire);
-        debug_assert!(x & (EXCLUSIVE_MASK | COUNT_MASK) == 0);
-        OlcVersion { x: x >> VERSION_SHIFT }
+        let x = self.0.fetch_or(Self::EXCLUSIVE_MASK, Acquire);
+        debug_assert!(x & (Self::EXCLUSIVE_MASK | Self::COUNT_MASK) == 0);
+        OlcVersion { x: x >> Self::VERSION_SHIFT }
     }
 
     /// returns version after unlocking
     pub fn unlock_exclusive(&self) -> OlcVersion {
         lock_track_set(self, None);
-        let fetched = self.0.fetch_add(EXCLUSIVE_MASK, Release);
-        debug_assert!(fetched & EXCLUSIVE_MASK != 0);
-        OlcVersion { x: (fetched + EXCLUSIVE_MASK) >> VERSION_SHIFT }
+        // clears the exclusive bit and increments the version, the carry out of the highest version bit is dropped
+        let fetched = self.0.fetch_add(Self::EXCLUSIVE_MASK, Release);
+        debug_assert!(fetched & Self::EXCLUSIVE_MASK != 0);
+        self.wake(fetched);
+        OlcVersion { x: fetched.wrapping_add(Self::EXCLUSIVE_MASK) >> Self::VERSION_SHIFT }
+    }
+
+    /// Releases the exclusive lock without incrementing the version,
+    /// so optimistic readers that started before it was locked still validate.
+    /// The caller must not have modified the protected data.
+    /// returns version after unlocking
+    pub fn unlock_exclusive_unmodified(&self) -> OlcVersion {
+        lock_track_set(self, None);
+        let fetched = self.0.fetch_and(!Self::EXCLUSIVE_MASK, Release);
+        debug_assert!(fetched & Self::EXCLUSIVE_MASK != 0);
+        self.wake(fetched);
+        OlcVersion { x: fetched >> Self::VERSION_SHIFT }
     }
 
+    /// Atomically turns a shared lock into an exclusive lock if the caller is the only reader.
+    /// Fails if there are other readers or a writer is waiting for the lock.
+    /// returns version before locking
+    pub fn try_upgrade_shared_to_exclusive(&self) -> Option<OlcVersion> {
+        let mut x = self.0.load(Relaxed);
+        loop {
+            debug_assert!(x & Self::COUNT_MASK != 0);
+            if x & (Self::COUNT_MASK | Self::HANDOFF_MASK | Self::EXCLUSIVE_MASK) != 1 {
+                return None;
+            }
+            match self.0.compare_exchange_weak(x, x - 1 + Self::EXCLUSIVE_MASK, Acquire, Relaxed) {
+                Ok(_) => {
+                    lock_track_set(self, Some(true));
+                    return Some(OlcVersion { x: x >> Self::VERSION_SHIFT });
+                }
+                Err(v) => x = v,
+            }
+        }
+    }
+
+    /// Atomically turns an exclusive lock into a shared lock, so no writer can get in between.
+    /// returns version after unlocking
+    pub fn downgrade_exclusive_to_shared(&self) -> OlcVersion {
+        lock_track_set(self, Some(false));
+        // clears the exclusive bit, increments the version and adds one reader
+        let fetched = self.0.fetch_add(Self::EXCLUSIVE_MASK + 1, Release);
+        debug_assert!(fetched & Self::EXCLUSIVE_MASK != 0);
+        debug_assert!(fetched & Self::COUNT_MASK == 0);
+        self.wake(fetched);
+        OlcVersion { x: fetched.wrapping_add(Self::EXCLUSIVE_MASK) >> Self::VERSION_SHIFT }
+    }
+
+    /// Like `downgrade_exclusive_to_shared`, but does not increment the version,
+    /// for locks that are only used as reader-writer locks.
+    pub(crate) fn downgrade_exclusive_to_shared_unmodified(&self) {
+        lock_track_set(self, Some(false));
+        // clears the exclusive bit and adds one reader
+        let fetched = self.0.fetch_sub(Self::EXCLUSIVE_MASK - 1, Release);
+        debug_assert!(fetched & Self::EXCLUSIVE_MASK != 0);
+        debug_assert!(fetched & Self::COUNT_MASK == 0);
+        self.wake(fetched);
+    }
+
+    /// Waits until no thread holds the lock exclusively.
+    /// Reads made afterwards are only consistent if `try_unlock_optimistic` succeeds with the returned version.
     pub fn lock_optimistic<F: VersionFilter>(&self, f: F) -> Result<F::R, F::E> {
         lock_track_check(self, None);
+        let mut round = 0;
         loop {
             let x = self.0.load(Acquire);
-            f.check(x >> VERSION_SHIFT)?;
-            if x & EXCLUSIVE_MASK == 0 {
-                return Ok(f.map_r(x >> VERSION_SHIFT));
+            f.check(x >> Self::VERSION_SHIFT)?;
+            if x & Self::EXCLUSIVE_MASK == 0 {
+                return Ok(f.map_r(x >> Self::VERSION_SHIFT));
             } else {
-                self.wait();
+                self.wait(x, &mut round, f.deadline());
             }
         }
     }
 
+    /// Like `lock_optimistic`, but returns `None` instead of waiting.
+    /// Also returns `None` if the version check fails.
+    pub fn try_lock_optimistic<F: VersionFilter>(&self, f: F) -> Option<F::R> {
+        lock_track_check(self, None);
+        let x = self.0.load(Acquire);
+        f.check(x >> Self::VERSION_SHIFT).ok()?;
+        if x & Self::EXCLUSIVE_MASK == 0 {
+            Some(f.map_r(x >> Self::VERSION_SHIFT))
+        } else {
+            None
+        }
+    }
+
+    /// Fails if the lock was locked exclusively since `lock_optimistic` returned `v`.
     pub fn try_unlock_optimistic(&self, v: OlcVersion) -> Result<(), OptimisticError> {
         fence(Acquire);
         let x = self.0.load(Relaxed);
-        if (x & !COUNT_MASK) == v.x << VERSION_SHIFT {
+        if x & Self::EXCLUSIVE_MASK == 0 && x >> Self::VERSION_SHIFT == v.x {
             Ok(())
         } else {
             Err(OptimisticError::new())
@@ -161,17 +716,54 @@ impl SeqLock {
     }
 }
 
+/// the half of the lock word that holds the count and the flags, futexes are only 32 bits wide
+fn futex_word(word: &AtomicU64) -> *const u32 {
+    let low_half = if cfg!(target_endian = "little") { 0 } else { 1 };
+    unsafe { (word.as_ptr() as *const u32).add(low_half) }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wait(word: &AtomicU64, expected: u64, timeout: Option<Duration>) {
+    let timeout = timeout.map(|t| libc::timespec {
+        tv_sec: t.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+        tv_nsec: t.subsec_nanos() as _,
+    });
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex_word(word),
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected as u32,
+            timeout.as_ref().map_or(std::ptr::null(), |t| t as *const libc::timespec),
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn futex_wake(word: &AtomicU64) {
+    unsafe {
+        libc::syscall(libc::SYS_futex, futex_word(word), libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG, i32::MAX);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wait(_word: &AtomicU64, _expected: u64, _timeout: Option<Duration>) {
+    std::thread::yield_now();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn futex_wake(_word: &AtomicU64) {}
+
 #[cfg(not(feature = "track-thread-locks"))]
-fn lock_track_check(_lock: &SeqLock, _mode: Option<bool>) {}
+pub(crate) fn lock_track_check<L>(_lock: &L, _mode: Option<bool>) {}
 #[cfg(not(feature = "track-thread-locks"))]
-fn lock_track_set(_lock: &SeqLock, _mode: Option<bool>) {}
+pub(crate) fn lock_track_set<L>(_lock: &L, _mode: Option<bool>) {}
 
 #[cfg(feature = "track-thread-locks")]
-use track_tread_locks::*;
+pub(crate) use track_tread_locks::*;
 
 #[cfg(feature = "track-thread-locks")]
 mod track_tread_locks {
-    use super::SeqLock;
     use std::cell::RefCell;
     use std::collections::HashMap;
 
@@ -179,16 +771,16 @@ mod track_tread_locks {
         static THREAD_LOCKS:RefCell<HashMap<usize,bool>>=Default::default();
     }
 
-    pub fn lock_track_check(lock: &SeqLock, mode: Option<bool>) {
-        let addr = (lock as *const SeqLock).addr();
+    pub fn lock_track_check<L>(lock: &L, mode: Option<bool>) {
+        let addr = (lock as *const L).addr();
         let existing = THREAD_LOCKS.with_borrow(|m| m.get(&addr).copied());
         if existing.is_some() {
             panic!("cannot acquire {} lock because {} is held by same thread", lock_name(mode), lock_name(existing))
         }
     }
 
-    pub fn lock_track_set(lock: &SeqLock, mode: Option<bool>) {
-        let addr = (lock as *const SeqLock).addr();
+    pub fn lock_track_set<L>(lock: &L, mode: Option<bool>) {
+        let addr = (lock as *const L).addr();
         THREAD_LOCKS.with_borrow_mut(|m| {
             if let Some(mode) = mode {
                 m.insert(addr, mode);
@@ -206,3 +798,199 @@ mod track_tread_locks {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HybridLock;
+
+    fn unlock_unmodified<L: PageLock>() {
+        let l = L::zeroed();
+        let Ok(v) = l.lock_optimistic(());
+        let Ok(_) = l.lock_exclusive(());
+        assert!(l.unlock_exclusive_unmodified() == v);
+        assert!(l.try_unlock_optimistic(v).is_ok());
+        let Ok(_) = l.lock_exclusive(());
+        assert!(l.unlock_exclusive() != v);
+        assert!(l.try_unlock_optimistic(v).is_err());
+    }
+
+    #[test]
+    fn unlock_exclusive_unmodified() {
+        unlock_unmodified::<SeqLock>();
+        unlock_unmodified::<HybridLock>();
+    }
+
+    fn version_wraps<const COUNT_BITS: u32>() {
+        type L<const C: u32> = SeqLock<ParkWait, C>;
+        let max = (1u64 << L::<COUNT_BITS>::VERSION_BITS) - 1;
+        let below_wrap =
+            || SeqLock::<ParkWait, COUNT_BITS>(AtomicU64::new(max << L::<COUNT_BITS>::VERSION_SHIFT), PhantomData);
+        let l = below_wrap();
+        let Ok(v) = l.lock_optimistic(());
+        assert_eq!(v.x, max);
+        let Ok(_) = l.lock_exclusive(());
+        assert_eq!(l.unlock_exclusive().x, 0);
+        assert!(l.try_unlock_optimistic(v).is_err());
+        let Ok(v) = l.lock_optimistic(());
+        assert_eq!(v.x, 0);
+        let Ok(_) = l.lock_exclusive(());
+        assert_eq!(l.unlock_exclusive().x, 1);
+        assert!(l.try_unlock_optimistic(v).is_err());
+        assert!(l.0.load(Relaxed) & (L::<COUNT_BITS>::COUNT_MASK | L::<COUNT_BITS>::EXCLUSIVE_MASK) == 0);
+        let l = below_wrap();
+        let Ok(_) = l.lock_exclusive(());
+        assert_eq!(l.downgrade_exclusive_to_shared().x, 0);
+        l.unlock_shared();
+        let Ok(v) = l.lock_optimistic(());
+        assert_eq!(v.x, 0);
+        assert!(l.try_unlock_optimistic(v).is_ok());
+        assert!(l.0.load(Relaxed) & L::<COUNT_BITS>::COUNT_MASK == 0);
+    }
+
+    #[test]
+    fn version_wrap() {
+        version_wraps::<10>();
+        version_wraps::<2>();
+        version_wraps::<21>();
+    }
+
+    /// Holds several shared locks from the test thread, which `track-thread-locks` rejects.
+    #[test]
+    #[cfg(not(feature = "track-thread-locks"))]
+    fn spilled_readers() {
+        type SmallLock = SeqLock<ParkWait, 2>;
+        let l = SmallLock::new();
+        let count = || l.0.load(Relaxed) & SmallLock::COUNT_MASK;
+        let spilled = || SPILLED_READERS.lock().unwrap().get(&l.addr()).copied();
+        let lock_shared = |n| {
+            for _ in 0..n {
+                let Ok(_) = l.lock_shared(());
+            }
+        };
+        let Ok(v) = l.lock_shared(());
+        lock_shared(5);
+        assert_eq!(count(), 3);
+        assert_eq!(spilled(), Some(3));
+        assert!(std::thread::scope(|s| s.spawn(|| l.try_lock_exclusive(()).is_none()).join().unwrap()));
+        // spilled readers leave first
+        for expected in [(3, Some(2)), (3, Some(1)), (3, None), (2, None), (1, None)] {
+            l.unlock_shared();
+            assert_eq!((count(), spilled()), expected);
+        }
+        assert_eq!(l.try_upgrade_shared_to_exclusive().map(|v| v.x), Some(v.x));
+        assert_eq!(l.unlock_exclusive().x, v.x + 1);
+        lock_shared(5);
+        assert_eq!(spilled(), Some(2));
+        // with other readers, none of them can upgrade
+        assert!(l.try_upgrade_shared_to_exclusive().is_none());
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                let Ok(v) = l.lock_exclusive(());
+                l.unlock_exclusive();
+                v
+            });
+            while l.0.load(Relaxed) & SmallLock::EXCLUSIVE_MASK == 0 {
+                std::thread::yield_now();
+            }
+            for _ in 0..5 {
+                assert!(!writer.is_finished());
+                l.unlock_shared();
+            }
+            assert_eq!(writer.join().unwrap().x, v.x + 1);
+        });
+        assert_eq!((count(), spilled()), (0, None));
+        assert_eq!(l.0.load(Relaxed) >> SmallLock::VERSION_SHIFT, v.x + 2);
+    }
+
+    type FairLock = SeqLock<Fair<ParkWait>>;
+
+    fn leak_fair() -> &'static FairLock {
+        Box::leak(Box::new(SeqLock::new()))
+    }
+
+    #[test]
+    fn fair_writers_in_ticket_order() {
+        let l = leak_fair();
+        let order = &*Box::leak(Box::new(Mutex::new(Vec::new())));
+        let Ok(_) = l.lock_exclusive(());
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let w = std::thread::spawn(move || {
+                    let Ok(_) = l.lock_exclusive(());
+                    order.lock().unwrap().push(i);
+                    l.unlock_exclusive();
+                });
+                while WRITER_QUEUES.lock().unwrap().get(&l.addr()).map_or(0, |t| t.next) <= i {
+                    std::thread::yield_now();
+                }
+                w
+            })
+            .collect();
+        assert!(l.0.load(Relaxed) & FairLock::HANDOFF_MASK != 0);
+        l.unlock_exclusive();
+        for w in writers {
+            w.join().unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3]);
+        assert!(!WRITER_QUEUES.lock().unwrap().contains_key(&l.addr()));
+        assert!(l.0.load(Relaxed) & (FairLock::HANDOFF_MASK | FairLock::EXCLUSIVE_MASK) == 0);
+    }
+
+    #[test]
+    fn fair_writers_give_up() {
+        let l = leak_fair();
+        let Ok(_) = l.lock_exclusive(());
+        let timeouts: Vec<_> = (0..4)
+            .map(|_| std::thread::spawn(move || l.lock_exclusive_timeout(Duration::from_millis(20)).is_err()))
+            .collect();
+        let waiter = std::thread::spawn(move || {
+            let Ok(_) = l.lock_exclusive(());
+            l.unlock_exclusive();
+        });
+        for t in timeouts {
+            assert!(t.join().unwrap());
+        }
+        l.unlock_exclusive();
+        waiter.join().unwrap();
+        let Ok(_) = l.lock_exclusive(());
+        l.unlock_exclusive();
+        assert!(!WRITER_QUEUES.lock().unwrap().contains_key(&l.addr()));
+        assert!(l.0.load(Relaxed) & (FairLock::HANDOFF_MASK | FairLock::EXCLUSIVE_MASK) == 0);
+    }
+
+    #[test]
+    fn fair_contention() {
+        let l = leak_fair();
+        let counter = &*Box::leak(Box::new(std::sync::atomic::AtomicU64::new(0)));
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    for i in 0..2000 {
+                        if (i + t) % 3 == 0 {
+                            let Ok(_) = l.lock_shared(());
+                            l.unlock_shared();
+                        } else if i % 7 == 0 {
+                            if l.lock_exclusive_timeout(Duration::from_micros(50)).is_ok() {
+                                counter.fetch_add(1, Relaxed);
+                                l.unlock_exclusive();
+                            }
+                        } else {
+                            let Ok(_) = l.lock_exclusive(());
+                            // a second writer inside would make this non-atomic update lose increments
+                            counter.store(counter.load(Relaxed) + 1, Relaxed);
+                            l.unlock_exclusive();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let Ok(v) = l.lock_optimistic(());
+        assert_eq!(v.x, counter.load(Relaxed));
+        assert!(!WRITER_QUEUES.lock().unwrap().contains_key(&l.addr()));
+        assert!(l.0.load(Relaxed) & (FairLock::COUNT_MASK | FairLock::HANDOFF_MASK | FairLock::EXCLUSIVE_MASK) == 0);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{OPtr, OlcErrorHandler};
+use std::cmp::Ordering;
+
+const COUNT_OFFSET: usize = 0;
+/// lowest offset used by the heap, records are allocated downwards from the end of the page
+const HEAP_START_OFFSET: usize = 2;
+const PREFIX_LEN_OFFSET: usize = 4;
+/// bytes in the heap that belong to live records, the rest is reclaimed by compaction
+const HEAP_USED_OFFSET: usize = 6;
+const HEADER_SIZE: usize = 8;
+/// offset, key length and value length of a record
+const SLOT_SIZE: usize = 6;
+
+/// An optimistic view of a page laid out by [`SlottedPageX`].
+///
+/// All offsets read from the page are bounds checked, corrupt offsets fail through `O`.
+/// Keys are returned without the prefix shared by all keys on the page.
+pub struct SlottedPageO<'a, O: OlcErrorHandler> {
+    page: OPtr<'a, [u8], O>,
+}
+
+impl<O: OlcErrorHandler> Clone for SlottedPageO<'_, O> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<O: OlcErrorHandler> Copy for SlottedPageO<'_, O> {}
+
+impl<'a, O: OlcErrorHandler> SlottedPageO<'a, O> {
+    pub fn new(page: OPtr<'a, [u8], O>) -> Self {
+        SlottedPageO { page }
+    }
+
+    pub fn count(self) -> usize {
+        count(self.page)
+    }
+
+    pub fn prefix(self) -> OPtr<'a, [u8], O> {
+        prefix(self.page)
+    }
+
+    /// key suffix of the `i`th record
+    pub fn key(self, i: usize) -> OPtr<'a, [u8], O> {
+        key(self.page, i)
+    }
+
+    pub fn value(self, i: usize) -> OPtr<'a, [u8], O> {
+        value(self.page, i)
+    }
+
+    /// Copies the prefix and the key suffix of the `i`th record.
+    pub fn load_key(self, i: usize) -> Vec<u8> {
+        let mut dst = self.prefix().load_slice_to_vec();
+        dst.extend_from_slice(&self.key(i).load_slice_to_vec());
+        dst
+    }
+
+    /// Index of the first record with a key not less than `key`, and whether that key is equal to `key`.
+    pub fn lower_bound(self, key: &[u8]) -> (usize, bool) {
+        lower_bound(self.page, key)
+    }
+
+    pub fn lookup(self, key: &[u8]) -> Option<OPtr<'a, [u8], O>> {
+        match self.lower_bound(key) {
+            (i, true) => Some(self.value(i)),
+            _ => None,
+        }
+    }
+}
+
+/// A slotted page for records with variable length keys and values.
+///
+/// The page starts with a header and the prefix shared by all keys, followed by a sorted array of slots.
+/// Records are stored in a heap growing downwards from the end of the page.
+/// Offsets are 16 bit, so pages may be at most 65535 bytes.
+/// Readers holding a shared or exclusive lock may use this directly, optimistic readers use [`SlottedPageO`].
+pub struct SlottedPageX<'a> {
+    page: &'a mut [u8],
+}
+
+impl<'a> SlottedPageX<'a> {
+    /// Wraps a page previously initialized with [`init`](Self::init).
+    pub fn new(page: &'a mut [u8]) -> Self {
+        SlottedPageX { page }
+    }
+
+    /// Formats `page` as an empty slotted page, all keys inserted later must start with `prefix`.
+    pub fn init(page: &'a mut [u8], prefix: &[u8]) -> Self {
+        assert!(page.len() <= u16::MAX as usize);
+        assert!(HEADER_SIZE + prefix.len() <= page.len());
+        let mut p = SlottedPageX { page };
+        p.set(COUNT_OFFSET, 0);
+        p.set(HEAP_START_OFFSET, p.page.len());
+        p.set(PREFIX_LEN_OFFSET, prefix.len());
+        p.set(HEAP_USED_OFFSET, 0);
+        p.page[HEADER_SIZE..][..prefix.len()].copy_from_slice(prefix);
+        p
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.page
+    }
+
+    fn set(&mut self, offset: usize, x: usize) {
+        self.page[offset..offset + 2].copy_from_slice(&(x as u16).to_ne_bytes());
+    }
+
+    pub fn count(&self) -> usize {
+        count(self.bytes())
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        prefix(self.bytes())
+    }
+
+    /// key suffix of the `i`th record
+    pub fn key(&self, i: usize) -> &[u8] {
+        key(self.bytes(), i)
+    }
+
+    pub fn value(&self, i: usize) -> &[u8] {
+        value(self.bytes(), i)
+    }
+
+    pub fn value_mut(&mut self, i: usize) -> &mut [u8] {
+        let (offset, key_len, value_len) = slot(self.bytes(), i);
+        &mut self.page[offset + key_len..][..value_len]
+    }
+
+    /// Index of the first record with a key not less than `key`, and whether that key is equal to `key`.
+    pub fn lower_bound(&self, key: &[u8]) -> (usize, bool) {
+        lower_bound(self.bytes(), key)
+    }
+
+    pub fn lookup(&self, key: &[u8]) -> Option<&[u8]> {
+        match self.lower_bound(key) {
+            (i, true) => Some(self.value(i)),
+            _ => None,
+        }
+    }
+
+    fn slots_end(&self) -> usize {
+        slot_offset(self.bytes(), self.count())
+    }
+
+    /// Bytes available for a new record and its slot without compacting.
+    pub fn free_space(&self) -> usize {
+        read_u16(self.bytes(), HEAP_START_OFFSET) - self.slots_end()
+    }
+
+    /// Bytes available for a new record and its slot after compacting.
+    pub fn free_space_after_compaction(&self) -> usize {
+        self.page.len() - self.slots_end() - read_u16(self.bytes(), HEAP_USED_OFFSET)
+    }
+
+    /// Inserts a record or replaces the value of an existing one.
+    /// Returns `false` without modifying the page if there is not enough space.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> bool {
+        let prefix_len = self.prefix().len();
+        assert!(key.starts_with(self.prefix()), "key does not match page prefix");
+        let suffix = &key[prefix_len..];
+        let (pos, found) = self.lower_bound(key);
+        let mut available = self.free_space_after_compaction();
+        if found {
+            let (_, key_len, value_len) = slot(self.bytes(), pos);
+            available += SLOT_SIZE + key_len + value_len;
+        }
+        if available < SLOT_SIZE + suffix.len() + value.len() {
+            return false;
+        }
+        if found {
+            self.remove_at(pos);
+        }
+        self.insert_at(pos, suffix, value);
+        true
+    }
+
+    fn insert_at(&mut self, pos: usize, suffix: &[u8], value: &[u8]) {
+        let record_len = suffix.len() + value.len();
+        if self.free_space() < SLOT_SIZE + record_len {
+            self.compact();
+        }
+        let count = self.count();
+        let heap_start = read_u16(self.bytes(), HEAP_START_OFFSET) - record_len;
+        self.page[heap_start..][..suffix.len()].copy_from_slice(suffix);
+        self.page[heap_start + suffix.len()..][..value.len()].copy_from_slice(value);
+        let slot_pos = slot_offset(self.bytes(), pos);
+        self.page.copy_within(slot_pos..self.slots_end(), slot_pos + SLOT_SIZE);
+        self.set(slot_pos, heap_start);
+        self.set(slot_pos + 2, suffix.len());
+        self.set(slot_pos + 4, value.len());
+        self.set(COUNT_OFFSET, count + 1);
+        self.set(HEAP_START_OFFSET, heap_start);
+        let heap_used = read_u16(self.bytes(), HEAP_USED_OFFSET);
+        self.set(HEAP_USED_OFFSET, heap_used + record_len);
+    }
+
+    /// Removes the record with the given key, returns `false` if there is none.
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        match self.lower_bound(key) {
+            (i, true) => {
+                self.remove_at(i);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn remove_at(&mut self, i: usize) {
+        let count = self.count();
+        assert!(i < count);
+        let (_, key_len, value_len) = slot(self.bytes(), i);
+        let slot_pos = slot_offset(self.bytes(), i);
+        self.page.copy_within(slot_pos + SLOT_SIZE..self.slots_end(), slot_pos);
+        self.set(COUNT_OFFSET, count - 1);
+        let heap_used = read_u16(self.bytes(), HEAP_USED_OFFSET);
+        self.set(HEAP_USED_OFFSET, heap_used - key_len - value_len);
+    }
+
+    /// Moves all records to the end of the page, so that the space freed by removals becomes usable.
+    pub fn compact(&mut self) {
+        let heap_start = read_u16(self.bytes(), HEAP_START_OFFSET);
+        let heap = self.page[heap_start..].to_vec();
+        let mut new_start = self.page.len();
+        for i in 0..self.count() {
+            let (offset, key_len, value_len) = slot(self.bytes(), i);
+            new_start -= key_len + value_len;
+            self.page[new_start..][..key_len + value_len]
+                .copy_from_slice(&heap[offset - heap_start..][..key_len + value_len]);
+            self.set(slot_offset(self.bytes(), i), new_start);
+        }
+        self.set(HEAP_START_OFFSET, new_start);
+    }
+}
+
+/// Page contents are read either optimistically or under a lock, so the layout is shared between both.
+trait PageBytes: Copy {
+    fn range(self, offset: usize, len: usize) -> Self;
+    fn len(self) -> usize;
+    fn load_u16(self) -> u16;
+    fn cmp_bytes(self, other: &[u8]) -> Ordering;
+}
+
+impl PageBytes for &[u8] {
+    fn range(self, offset: usize, len: usize) -> Self {
+        &self[offset..][..len]
+    }
+
+    fn len(self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn load_u16(self) -> u16 {
+        u16::from_ne_bytes(self.try_into().unwrap())
+    }
+
+    fn cmp_bytes(self, other: &[u8]) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+impl<O: OlcErrorHandler> PageBytes for OPtr<'_, [u8], O> {
+    fn range(self, offset: usize, len: usize) -> Self {
+        self.sub(offset, len)
+    }
+
+    fn len(self) -> usize {
+        OPtr::len(self)
+    }
+
+    fn load_u16(self) -> u16 {
+        let mut x = [0u8; 2];
+        self.load_bytes(&mut x);
+        u16::from_ne_bytes(x)
+    }
+
+    fn cmp_bytes(self, other: &[u8]) -> Ordering {
+        self.mem_cmp(other)
+    }
+}
+
+fn read_u16(page: impl PageBytes, offset: usize) -> usize {
+    page.range(offset, 2).load_u16() as usize
+}
+
+fn count(page: impl PageBytes) -> usize {
+    read_u16(page, COUNT_OFFSET)
+}
+
+fn prefix<P: PageBytes>(page: P) -> P {
+    page.range(HEADER_SIZE, read_u16(page, PREFIX_LEN_OFFSET))
+}
+
+fn slot_offset(page: impl PageBytes, i: usize) -> usize {
+    HEADER_SIZE + read_u16(page, PREFIX_LEN_OFFSET) + i * SLOT_SIZE
+}
+
+/// offset, key length and value length of the `i`th record
+fn slot(page: impl PageBytes, i: usize) -> (usize, usize, usize) {
+    let slot = slot_offset(page, i);
+    (read_u16(page, slot), read_u16(page, slot + 2), read_u16(page, slot + 4))
+}
+
+fn key<P: PageBytes>(page: P, i: usize) -> P {
+    let (offset, key_len, _) = slot(page, i);
+    page.range(offset, key_len)
+}
+
+fn value<P: PageBytes>(page: P, i: usize) -> P {
+    let (offset, key_len, value_len) = slot(page, i);
+    page.range(offset + key_len, value_len)
+}
+
+fn lower_bound(page: impl PageBytes, key: &[u8]) -> (usize, bool) {
+    let prefix = prefix(page);
+    let count = count(page);
+    let prefix_len = prefix.len();
+    match prefix.cmp_bytes(&key[..prefix_len.min(key.len())]) {
+        Ordering::Less => return (count, false),
+        Ordering::Greater => return (0, false),
+        Ordering::Equal => {}
+    }
+    let suffix = &key[prefix_len..];
+    let (mut lower, mut upper) = (0, count);
+    while lower < upper {
+        let mid = (lower + upper) / 2;
+        match self::key(page, mid).cmp_bytes(suffix) {
+            Ordering::Less => lower = mid + 1,
+            Ordering::Greater => upper = mid,
+            Ordering::Equal => return (mid, true),
+        }
+    }
+    (lower, false)
+}
@@ -0,0 +1,341 @@
+use crate::buffer_manager::CommonSeqLockBM;
+use crate::disk_bm::read_page;
+use crate::seqlock::{PageLock, SeqLock};
+use crate::{AllocError, PageId, UnwindOlcEh};
+use bytemuck::Zeroable;
+use std::cell::UnsafeCell;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+const RESIDENT: u8 = 1;
+const REFERENCED: u8 = 2;
+/// written since it was last written back, only set on resident pages
+const DIRTY: u8 = 4;
+/// number of full clock rotations before giving up on finding an evictable page
+const EVICT_ROUNDS: usize = 8;
+
+/// A buffer manager in the style of vmcache.
+///
+/// A virtual memory range large enough for every page is reserved up front, so page `n` always lives at
+/// `base + n * size_of::<P>()`.
+/// At most `pool_size` pages are backed by physical memory, others are evicted using `madvise(MADV_DONTNEED)`
+/// and read back with `pread`.
+/// Only pages written through an exclusive guard since they were loaded are written back.
+/// The size of `P` must be a multiple of the OS page size.
+pub struct VmCacheBm<P, L: PageLock = SeqLock> {
+    base: *mut UnsafeCell<P>,
+    virtual_pages: usize,
+    locks: Box<[L]>,
+    state: Box<[AtomicU8]>,
+    resident_count: AtomicUsize,
+    pool_size: usize,
+    free_pages: Mutex<Vec<u64>>,
+    page_count: AtomicU64,
+    clock_hand: AtomicU64,
+    file: File,
+    _p: PhantomData<P>,
+}
+
+unsafe impl<P, L: PageLock> Sync for VmCacheBm<P, L> {}
+unsafe impl<P, L: PageLock> Send for VmCacheBm<P, L> {}
+
+impl<P: Zeroable, L: PageLock> VmCacheBm<P, L> {
+    /// Opens or creates the page file at `path`.
+    /// `pool_size` is the number of pages kept in memory, `virtual_pages` the maximum number of pages in the file.
+    pub fn open(path: impl AsRef<Path>, pool_size: usize, virtual_pages: usize) -> io::Result<Self> {
+        let os_page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        assert!(
+            size_of::<P>() > 0 && size_of::<P>().is_multiple_of(os_page_size),
+            "page size must be a multiple of {os_page_size}"
+        );
+        assert!(pool_size > 0);
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let page_count = file.metadata()?.len().div_ceil(size_of::<P>() as u64);
+        if page_count > virtual_pages as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "page file exceeds virtual memory range"));
+        }
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size_of::<P>() * virtual_pages,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            // large zeroed allocations are lazily backed by the OS as well
+            Ok(VmCacheBm {
+                base: base as *mut UnsafeCell<P>,
+                virtual_pages,
+                locks: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(virtual_pages)),
+                state: Box::<[MaybeUninit<_>]>::assume_init(Box::new_zeroed_slice(virtual_pages)),
+                resident_count: AtomicUsize::new(0),
+                pool_size,
+                free_pages: Mutex::new(Vec::new()),
+                page_count: AtomicU64::new(page_count),
+                clock_hand: AtomicU64::new(0),
+                file,
+                _p: PhantomData,
+            })
+        }
+    }
+}
+
+impl<P, L: PageLock> VmCacheBm<P, L> {
+    /// Writes all dirty pages back to the file and syncs it.
+    pub fn flush(&self) -> io::Result<()> {
+        for pid in 0..self.page_count.load(Relaxed).min(self.virtual_pages as u64) {
+            if self.state[pid as usize].load(Relaxed) & DIRTY == 0 {
+                continue;
+            }
+            let Ok(_) = self.locks[pid as usize].lock_shared(());
+            let result =
+                if self.state[pid as usize].load(Relaxed) & DIRTY != 0 { self.write_back(pid) } else { Ok(()) };
+            self.locks[pid as usize].unlock_shared();
+            result?;
+        }
+        self.file.sync_data()
+    }
+
+    /// Writes `pid` back to the file if it is dirty, returns whether it was.
+    /// Unlike [`flush`](Self::flush), this does not sync the file.
+    pub fn flush_page(&self, pid: PageId) -> io::Result<bool> {
+        let lock = &self.locks[pid.x as usize];
+        let Ok(_) = lock.lock_shared(());
+        let dirty = self.state[pid.x as usize].load(Relaxed) & DIRTY != 0;
+        let result = if dirty { self.write_back(pid.x) } else { Ok(()) };
+        lock.unlock_shared();
+        result.map(|()| dirty)
+    }
+
+    /// Returns the pages that were written since they were last written back.
+    /// Their dirty bits are cleared by writing them back with [`flush`](Self::flush) or [`flush_page`](Self::flush_page).
+    pub fn dirty_pages(&self) -> Vec<PageId> {
+        (0..self.page_count.load(Relaxed).min(self.virtual_pages as u64))
+            .filter(|&pid| self.state[pid as usize].load(Relaxed) & DIRTY != 0)
+            .map(|pid| PageId { x: pid })
+            .collect()
+    }
+
+    /// Requires a lock on `pid`.
+    fn write_back(&self, pid: u64) -> io::Result<()> {
+        self.file.write_all_at(self.page_bytes(pid), self.file_offset(pid))?;
+        self.state[pid as usize].fetch_and(!DIRTY, Relaxed);
+        Ok(())
+    }
+
+    fn file_offset(&self, pid: u64) -> u64 {
+        pid * size_of::<P>() as u64
+    }
+
+    /// Requires a lock on `pid`.
+    fn page_bytes(&self, pid: u64) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.base.add(pid as usize) as *const u8, size_of::<P>()) }
+    }
+
+    /// Requires an exclusive lock on `pid`.
+    #[allow(clippy::mut_from_ref)]
+    fn page_bytes_mut(&self, pid: u64) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.base.add(pid as usize) as *mut u8, size_of::<P>()) }
+    }
+
+    /// requires exclusive lock on pid
+    fn make_resident(&self, pid: u64) -> Result<(), AllocError> {
+        if self.resident_count.fetch_add(1, Relaxed) >= self.pool_size {
+            if let Err(e) = self.evict() {
+                self.resident_count.fetch_sub(1, Relaxed);
+                return Err(e);
+            }
+        }
+        self.state[pid as usize].store(RESIDENT | REFERENCED, Release);
+        Ok(())
+    }
+
+    /// requires exclusive lock on pid
+    fn release_memory(&self, pid: u64) {
+        self.state[pid as usize].store(0, Relaxed);
+        unsafe {
+            libc::madvise(self.base.add(pid as usize) as *mut libc::c_void, size_of::<P>(), libc::MADV_DONTNEED);
+        }
+        self.resident_count.fetch_sub(1, Relaxed);
+    }
+
+    fn evict(&self) -> Result<(), AllocError> {
+        let page_count = self.page_count.load(Relaxed).min(self.virtual_pages as u64);
+        for _ in 0..page_count as usize * EVICT_ROUNDS {
+            let pid = self.clock_hand.fetch_add(1, Relaxed) % page_count;
+            let state = &self.state[pid as usize];
+            let s = state.load(Relaxed);
+            if s & RESIDENT == 0 {
+                continue;
+            }
+            if s & REFERENCED != 0 {
+                state.fetch_and(!REFERENCED, Relaxed);
+                continue;
+            }
+            let lock = &self.locks[pid as usize];
+            if lock.try_lock_exclusive(()).is_none() {
+                continue;
+            }
+            if state.load(Relaxed) & RESIDENT == 0 {
+                // evicted in the meantime, optimistic readers of the page remain valid
+                lock.unlock_exclusive_unmodified();
+                continue;
+            }
+            if state.load(Relaxed) & DIRTY != 0 {
+                if let Err(e) = self.write_back(pid) {
+                    // the page stays resident and dirty
+                    lock.unlock_exclusive_unmodified();
+                    return Err(AllocError::Io(e.kind()));
+                }
+            }
+            self.release_memory(pid);
+            lock.unlock_exclusive();
+            return Ok(());
+        }
+        Err(AllocError::PoolExhausted)
+    }
+}
+
+impl<P, L: PageLock> Drop for VmCacheBm<P, L> {
+    fn drop(&mut self) {
+        let result = self.flush();
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, size_of::<P>() * self.virtual_pages);
+        }
+        if let Err(e) = result {
+            if !std::thread::panicking() {
+                panic!("failed to flush pages: {e}");
+            }
+        }
+    }
+}
+
+impl<'bm, P, L: PageLock> CommonSeqLockBM<'bm> for &'bm VmCacheBm<P, L> {
+    type Page = P;
+    type OlcEH = UnwindOlcEh;
+    type Lock = L;
+
+    fn pid_from_address(self, address: usize) -> PageId {
+        let start = self.base.addr();
+        debug_assert!(address >= start);
+        debug_assert!(address < start + size_of::<P>() * self.virtual_pages);
+        let offset = address - start;
+        assert_eq!(offset % size_of::<P>(), 0);
+        PageId { x: (offset / size_of::<P>()) as u64 }
+    }
+
+    fn try_alloc(self) -> Result<PageId, AllocError> {
+        let pid = {
+            let mut free_pages = self.free_pages.lock().unwrap();
+            match free_pages.pop() {
+                Some(pid) => pid,
+                None => {
+                    let pid = self.page_count.load(Relaxed);
+                    if pid >= self.virtual_pages as u64 {
+                        return Err(AllocError::OutOfPages);
+                    }
+                    self.page_count.store(pid + 1, Relaxed);
+                    pid
+                }
+            }
+        };
+        // not force_lock_exclusive, eviction may still hold the lock of a deallocated page
+        let Ok(_) = self.locks[pid as usize].lock_exclusive(());
+        if self.state[pid as usize].load(Relaxed) & RESIDENT == 0 {
+            if let Err(e) = self.make_resident(pid) {
+                self.locks[pid as usize].unlock_exclusive();
+                self.free_pages.lock().unwrap().push(pid);
+                return Err(e);
+            }
+        }
+        self.page_bytes_mut(pid).fill(0);
+        // the file may still hold the contents of a deallocated page
+        self.state[pid as usize].fetch_or(DIRTY, Relaxed);
+        Ok(PageId { x: pid })
+    }
+
+    fn dealloc(self, pid: PageId) {
+        self.release_memory(pid.x);
+        self.locks[pid.x as usize].unlock_exclusive();
+        self.free_pages.lock().unwrap().push(pid.x);
+    }
+
+    fn page(self, pid: PageId) -> &'bm UnsafeCell<Self::Page> {
+        assert!(pid.x < self.virtual_pages as u64);
+        unsafe { &*self.base.add(pid.x as usize) }
+    }
+
+    fn lock(self, pid: PageId) -> &'bm L {
+        &self.locks[pid.x as usize]
+    }
+
+    fn fix(self, pid: PageId, deadline: Option<Instant>) -> bool {
+        let state = &self.state[pid.x as usize];
+        loop {
+            if state.load(Relaxed) & RESIDENT != 0 {
+                state.fetch_or(REFERENCED, Relaxed);
+                return true;
+            }
+            let lock = &self.locks[pid.x as usize];
+            if lock.try_lock_exclusive(()).is_some() {
+                if state.load(Relaxed) & RESIDENT != 0 {
+                    lock.unlock_exclusive_unmodified();
+                    return true;
+                }
+                match self.make_resident(pid.x) {
+                    Ok(()) => {
+                        if let Err(e) = read_page(&self.file, self.page_bytes_mut(pid.x), self.file_offset(pid.x)) {
+                            panic!("failed to read page {}: {e}", pid.x);
+                        }
+                        lock.unlock_exclusive();
+                        return true;
+                    }
+                    Err(AllocError::PoolExhausted) => {
+                        lock.unlock_exclusive_unmodified();
+                        // there is nothing to park on until a resident page is unlocked
+                        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            return false;
+                        }
+                        std::thread::yield_now();
+                        continue;
+                    }
+                    Err(e) => panic!("failed to load page {}: {e}", pid.x),
+                }
+            }
+            // another thread is loading or evicting the page, park until it is done
+            let waited = match deadline {
+                Some(deadline) => lock.lock_shared(deadline).is_ok(),
+                None => {
+                    let Ok(_) = lock.lock_shared(());
+                    true
+                }
+            };
+            if !waited {
+                return false;
+            }
+            lock.unlock_shared();
+        }
+    }
+
+    fn is_resident(self, pid: PageId) -> bool {
+        self.state[pid.x as usize].load(Acquire) & RESIDENT != 0
+    }
+
+    fn mark_dirty(self, pid: PageId) {
+        self.state[pid.x as usize].fetch_or(DIRTY, Relaxed);
+    }
+}
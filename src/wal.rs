@@ -0,0 +1,501 @@
+use crate::PageId;
+use bytemuck::{Pod, Zeroable};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::Mutex;
+
+/// written at the start of the log, so no record has LSN 0
+const MAGIC: [u8; 8] = *b"olcwal01";
+/// offset of the LSN of the last complete checkpoint, follows the magic
+const CHECKPOINT_OFFSET: u64 = 8;
+const HEADER_SIZE: u64 = 16;
+/// length, kind and checksum
+const RECORD_HEADER_SIZE: usize = 16;
+/// the buffer is written out once it grows beyond this
+const BUFFER_FLUSH_SIZE: usize = 1 << 20;
+
+const KIND_UPDATE: u32 = 1;
+const KIND_COMMIT: u32 = 2;
+const KIND_COMPENSATION: u32 = 3;
+const KIND_ABORT: u32 = 4;
+const KIND_CHECKPOINT: u32 = 5;
+
+/// Log sequence number, the offset of a record in the log file.
+///
+/// `Lsn { x: 0 }` precedes all records, it is the LSN of pages that were never modified with logging.
+#[derive(Debug, Zeroable, Pod, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct Lsn {
+    pub x: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LogRecord {
+    /// `redo` was written at `offset` of the page, replacing `undo`.
+    Update {
+        txn: u64,
+        page_id: PageId,
+        offset: u32,
+        undo: Vec<u8>,
+        redo: Vec<u8>,
+    },
+    /// Written when undoing the update at `compensated`, updates of `txn` after it have been undone already.
+    /// Compensations are only redone, never undone.
+    Compensation {
+        txn: u64,
+        page_id: PageId,
+        offset: u32,
+        compensated: Lsn,
+        redo: Vec<u8>,
+    },
+    Commit {
+        txn: u64,
+    },
+    /// All updates of `txn` have been undone.
+    Abort {
+        txn: u64,
+    },
+    /// The dirty pages with the LSN of their oldest change not on disk,
+    /// and the active transactions with the LSN of their first update, as of `begin`.
+    Checkpoint {
+        begin: Lsn,
+        dirty_pages: Vec<(PageId, Lsn)>,
+        active_txns: Vec<(u64, Lsn)>,
+    },
+}
+
+impl LogRecord {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.resize(start + RECORD_HEADER_SIZE, 0);
+        let kind = match self {
+            LogRecord::Update { txn, page_id, offset, undo, redo } => {
+                out.extend_from_slice(&txn.to_ne_bytes());
+                out.extend_from_slice(&page_id.x.to_ne_bytes());
+                out.extend_from_slice(&offset.to_ne_bytes());
+                out.extend_from_slice(&(undo.len() as u32).to_ne_bytes());
+                out.extend_from_slice(undo);
+                out.extend_from_slice(redo);
+                KIND_UPDATE
+            }
+            LogRecord::Compensation { txn, page_id, offset, compensated, redo } => {
+                out.extend_from_slice(&txn.to_ne_bytes());
+                out.extend_from_slice(&page_id.x.to_ne_bytes());
+                out.extend_from_slice(&offset.to_ne_bytes());
+                out.extend_from_slice(&compensated.x.to_ne_bytes());
+                out.extend_from_slice(redo);
+                KIND_COMPENSATION
+            }
+            LogRecord::Commit { txn } => {
+                out.extend_from_slice(&txn.to_ne_bytes());
+                KIND_COMMIT
+            }
+            LogRecord::Abort { txn } => {
+                out.extend_from_slice(&txn.to_ne_bytes());
+                KIND_ABORT
+            }
+            LogRecord::Checkpoint { begin, dirty_pages, active_txns } => {
+                out.extend_from_slice(&begin.x.to_ne_bytes());
+                out.extend_from_slice(&(dirty_pages.len() as u32).to_ne_bytes());
+                out.extend_from_slice(&(active_txns.len() as u32).to_ne_bytes());
+                for (pid, lsn) in dirty_pages {
+                    out.extend_from_slice(&pid.x.to_ne_bytes());
+                    out.extend_from_slice(&lsn.x.to_ne_bytes());
+                }
+                for (txn, lsn) in active_txns {
+                    out.extend_from_slice(&txn.to_ne_bytes());
+                    out.extend_from_slice(&lsn.x.to_ne_bytes());
+                }
+                KIND_CHECKPOINT
+            }
+        };
+        let len = (out.len() - start) as u32;
+        out[start..start + 4].copy_from_slice(&len.to_ne_bytes());
+        out[start + 4..start + 8].copy_from_slice(&kind.to_ne_bytes());
+        let checksum = checksum(&out[start..start + 8], &out[start + RECORD_HEADER_SIZE..]);
+        out[start + 8..start + 16].copy_from_slice(&checksum.to_ne_bytes());
+    }
+
+    /// Decodes the record at the start of `bytes` and returns its length.
+    /// Returns `None` for incomplete or corrupt records, as found at the end of the log after a crash.
+    fn decode(bytes: &[u8]) -> Option<(LogRecord, usize)> {
+        let header = bytes.get(..RECORD_HEADER_SIZE)?;
+        let len = u32::from_ne_bytes(header[0..4].try_into().unwrap()) as usize;
+        let kind = u32::from_ne_bytes(header[4..8].try_into().unwrap());
+        if len < RECORD_HEADER_SIZE {
+            // a corrupt length, the header alone is longer
+            return None;
+        }
+        let body = bytes.get(RECORD_HEADER_SIZE..len)?;
+        if checksum(&header[0..8], body) != u64::from_ne_bytes(header[8..16].try_into().unwrap()) {
+            return None;
+        }
+        let mut reader = Reader { bytes: body };
+        let record = match kind {
+            KIND_UPDATE => {
+                let txn = reader.u64()?;
+                let page_id = PageId { x: reader.u64()? };
+                let offset = reader.u32()?;
+                let undo_len = reader.u32()? as usize;
+                let undo = reader.take(undo_len)?.to_vec();
+                let redo = reader.take(reader.bytes.len())?.to_vec();
+                LogRecord::Update { txn, page_id, offset, undo, redo }
+            }
+            KIND_COMPENSATION => {
+                let txn = reader.u64()?;
+                let page_id = PageId { x: reader.u64()? };
+                let offset = reader.u32()?;
+                let compensated = Lsn { x: reader.u64()? };
+                let redo = reader.take(reader.bytes.len())?.to_vec();
+                LogRecord::Compensation { txn, page_id, offset, compensated, redo }
+            }
+            KIND_COMMIT => LogRecord::Commit { txn: reader.u64()? },
+            KIND_ABORT => LogRecord::Abort { txn: reader.u64()? },
+            KIND_CHECKPOINT => {
+                let begin = Lsn { x: reader.u64()? };
+                let dirty_count = reader.u32()?;
+                let active_count = reader.u32()?;
+                let dirty_pages = (0..dirty_count)
+                    .map(|_| Some((PageId { x: reader.u64()? }, Lsn { x: reader.u64()? })))
+                    .collect::<Option<_>>()?;
+                let active_txns = (0..active_count)
+                    .map(|_| Some((reader.u64()?, Lsn { x: reader.u64()? })))
+                    .collect::<Option<_>>()?;
+                LogRecord::Checkpoint { begin, dirty_pages, active_txns }
+            }
+            _ => return None,
+        };
+        Some((record, len))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.bytes.split_at_checked(len)?;
+        self.bytes = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_ne_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// FNV-1a over the length and kind of a record followed by its body
+fn checksum(header: &[u8], body: &[u8]) -> u64 {
+    header.iter().chain(body).fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Decodes records from the start of `bytes` until the first incomplete or corrupt one.
+/// Returns the records with their LSNs, given that `bytes` starts at `start`, and the end of the last record.
+fn decode_all(bytes: &[u8], start: u64) -> (Vec<(Lsn, LogRecord)>, u64) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while let Some((record, len)) = LogRecord::decode(&bytes[offset..]) {
+        records.push((Lsn { x: start + offset as u64 }, record));
+        offset += len;
+    }
+    (records, start + offset as u64)
+}
+
+/// A write-ahead log stored in a file.
+///
+/// Records are appended to an in-memory buffer and become durable once the buffer is flushed.
+/// A buffer manager writing back a page must first flush the log up to the page's LSN.
+/// Opening a log discards an incomplete record at its end, as left behind by a crash during a flush.
+///
+/// The log tracks which pages have changes that were not written back and which transactions are active,
+/// so checkpoints can bound the part of the log that recovery has to read.
+pub struct Wal {
+    file: File,
+    buffer: Mutex<LogBuffer>,
+    /// end of the durable part of the log
+    flushed: AtomicU64,
+    last_checkpoint: AtomicU64,
+}
+
+struct LogBuffer {
+    bytes: Vec<u8>,
+    /// file offset of `bytes[0]`
+    start: u64,
+    /// page id to the LSN of the oldest change not written back
+    dirty_pages: HashMap<u64, Lsn>,
+    /// transaction to the LSN of its first update
+    active_txns: HashMap<u64, Lsn>,
+}
+
+impl Wal {
+    /// Opens or creates the log file at `path`, new records are appended after the existing ones.
+    /// Only the log from the last checkpoint on is read to find its end, the part before it is durable.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let len = file.metadata()?.len();
+        let (end, last_checkpoint) = if len == 0 {
+            let mut header = [0; HEADER_SIZE as usize];
+            header[..MAGIC.len()].copy_from_slice(&MAGIC);
+            file.write_all_at(&header, 0)?;
+            (HEADER_SIZE, 0)
+        } else {
+            let mut header = [0; HEADER_SIZE as usize];
+            if len >= HEADER_SIZE {
+                file.read_exact_at(&mut header, 0)?;
+            }
+            if !header.starts_with(&MAGIC) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a log file"));
+            }
+            let last_checkpoint = u64::from_ne_bytes(header[CHECKPOINT_OFFSET as usize..][..8].try_into().unwrap());
+            let start = if last_checkpoint == 0 { HEADER_SIZE } else { last_checkpoint };
+            if start < HEADER_SIZE || start > len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid checkpoint LSN"));
+            }
+            let mut tail = vec![0; (len - start) as usize];
+            file.read_exact_at(&mut tail, start)?;
+            (decode_all(&tail, start).1, last_checkpoint)
+        };
+        file.set_len(end)?;
+        file.sync_data()?;
+        let buffer =
+            LogBuffer { bytes: Vec::new(), start: end, dirty_pages: HashMap::new(), active_txns: HashMap::new() };
+        Ok(Wal {
+            file,
+            buffer: Mutex::new(buffer),
+            flushed: AtomicU64::new(end),
+            last_checkpoint: AtomicU64::new(last_checkpoint),
+        })
+    }
+
+    /// Appends `record` to the log buffer and returns its LSN.
+    /// Fails if the buffer is full and could not be written, in which case `record` is not appended.
+    pub fn append(&self, record: &LogRecord) -> io::Result<Lsn> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.bytes.len() >= BUFFER_FLUSH_SIZE {
+            self.write_buffer(&mut buffer)?;
+        }
+        let lsn = Lsn { x: buffer.start + buffer.bytes.len() as u64 };
+        record.encode(&mut buffer.bytes);
+        match record {
+            LogRecord::Update { txn, page_id, .. } | LogRecord::Compensation { txn, page_id, .. } => {
+                buffer.dirty_pages.entry(page_id.x).or_insert(lsn);
+                buffer.active_txns.entry(*txn).or_insert(lsn);
+            }
+            LogRecord::Commit { txn } | LogRecord::Abort { txn } => {
+                buffer.active_txns.remove(txn);
+            }
+            LogRecord::Checkpoint { .. } => (),
+        }
+        Ok(lsn)
+    }
+
+    /// Appends a commit record for `txn` and waits until it is durable.
+    pub fn commit(&self, txn: u64) -> io::Result<Lsn> {
+        let lsn = self.append(&LogRecord::Commit { txn })?;
+        self.flush(lsn)?;
+        Ok(lsn)
+    }
+
+    /// Makes the record at `lsn` and all records before it durable.
+    pub fn flush(&self, lsn: Lsn) -> io::Result<()> {
+        if lsn.x < self.flushed.load(Acquire) {
+            return Ok(());
+        }
+        let mut buffer = self.buffer.lock().unwrap();
+        // another thread may have flushed while we were waiting
+        if lsn.x < self.flushed.load(Acquire) {
+            return Ok(());
+        }
+        self.write_buffer(&mut buffer)
+    }
+
+    /// Makes all appended records durable.
+    pub fn flush_all(&self) -> io::Result<()> {
+        self.write_buffer(&mut self.buffer.lock().unwrap())
+    }
+
+    /// Records before this LSN are durable.
+    pub fn flushed_lsn(&self) -> Lsn {
+        Lsn { x: self.flushed.load(Acquire) }
+    }
+
+    fn write_buffer(&self, buffer: &mut LogBuffer) -> io::Result<()> {
+        if buffer.bytes.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all_at(&buffer.bytes, buffer.start)?;
+        self.file.sync_data()?;
+        buffer.start += buffer.bytes.len() as u64;
+        buffer.bytes.clear();
+        self.flushed.store(buffer.start, Release);
+        Ok(())
+    }
+
+    /// Reads all durable records.
+    pub fn records(&self) -> io::Result<Vec<(Lsn, LogRecord)>> {
+        self.records_from(Lsn { x: HEADER_SIZE })
+    }
+
+    /// Reads the durable records starting with the one at `lsn`.
+    pub fn records_from(&self, lsn: Lsn) -> io::Result<Vec<(Lsn, LogRecord)>> {
+        let mut contents = vec![0; self.flushed.load(Acquire).saturating_sub(lsn.x) as usize];
+        self.file.read_exact_at(&mut contents, lsn.x)?;
+        Ok(decode_all(&contents, lsn.x).0)
+    }
+
+    /// The LSN of the checkpoint record written by the last complete checkpoint.
+    pub fn last_checkpoint(&self) -> Option<Lsn> {
+        let lsn = self.last_checkpoint.load(Acquire);
+        (lsn != 0).then_some(Lsn { x: lsn })
+    }
+
+    /// The pages with changes that were not written back, with the LSN of the oldest such change.
+    pub fn dirty_pages(&self) -> Vec<(PageId, Lsn)> {
+        let buffer = self.buffer.lock().unwrap();
+        buffer.dirty_pages.iter().map(|(&pid, &lsn)| (PageId { x: pid }, lsn)).collect()
+    }
+
+    /// Records that the change at `lsn` was applied to `pid` without appending it, as done by recovery.
+    pub(crate) fn mark_dirty(&self, pid: PageId, lsn: Lsn) {
+        self.buffer.lock().unwrap().dirty_pages.entry(pid.x).or_insert(lsn);
+    }
+
+    /// Called after `pid` was written back or freed while locked, so no change can have been made in between.
+    pub(crate) fn page_written(&self, pid: PageId) {
+        self.buffer.lock().unwrap().dirty_pages.remove(&pid.x);
+    }
+
+    /// Snapshots the dirty pages and active transactions as of the current end of the log.
+    /// The snapshot becomes a checkpoint once passed to [`end_checkpoint`](Self::end_checkpoint).
+    pub(crate) fn begin_checkpoint(&self) -> LogRecord {
+        let buffer = self.buffer.lock().unwrap();
+        LogRecord::Checkpoint {
+            begin: Lsn { x: buffer.start + buffer.bytes.len() as u64 },
+            dirty_pages: buffer.dirty_pages.iter().map(|(&pid, &lsn)| (PageId { x: pid }, lsn)).collect(),
+            active_txns: buffer.active_txns.iter().map(|(&txn, &lsn)| (txn, lsn)).collect(),
+        }
+    }
+
+    /// Appends the checkpoint record and makes it the starting point of recovery.
+    /// Pages written back before the snapshot was taken must be durable by now.
+    pub(crate) fn end_checkpoint(&self, checkpoint: &LogRecord) -> io::Result<Lsn> {
+        let lsn = self.append(checkpoint)?;
+        self.flush(lsn)?;
+        self.file.write_all_at(&lsn.x.to_ne_bytes(), CHECKPOINT_OFFSET)?;
+        self.file.sync_data()?;
+        self.last_checkpoint.store(lsn.x, Release);
+        Ok(lsn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<LogRecord> {
+        vec![
+            LogRecord::Update { txn: 1, page_id: PageId { x: 2 }, offset: 3, undo: vec![4, 5], redo: vec![6, 7] },
+            LogRecord::Update { txn: 1, page_id: PageId { x: 2 }, offset: 0, undo: vec![], redo: vec![] },
+            LogRecord::Compensation {
+                txn: 1,
+                page_id: PageId { x: 2 },
+                offset: 3,
+                compensated: Lsn { x: 16 },
+                redo: vec![4, 5],
+            },
+            LogRecord::Commit { txn: u64::MAX },
+            LogRecord::Abort { txn: 8 },
+            LogRecord::Checkpoint { begin: Lsn { x: 9 }, dirty_pages: vec![], active_txns: vec![] },
+            LogRecord::Checkpoint {
+                begin: Lsn { x: 9 },
+                dirty_pages: vec![(PageId { x: 10 }, Lsn { x: 11 }), (PageId { x: 12 }, Lsn { x: 13 })],
+                active_txns: vec![(14, Lsn { x: 15 })],
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut log = Vec::new();
+        let mut lsns = Vec::new();
+        for record in records() {
+            let mut bytes = Vec::new();
+            record.encode(&mut bytes);
+            assert_eq!(LogRecord::decode(&bytes), Some((record, bytes.len())));
+            lsns.push(Lsn { x: HEADER_SIZE + log.len() as u64 });
+            log.extend_from_slice(&bytes);
+        }
+        let (decoded, end) = decode_all(&log, HEADER_SIZE);
+        assert_eq!(decoded, lsns.into_iter().zip(records()).collect::<Vec<_>>());
+        assert_eq!(end, HEADER_SIZE + log.len() as u64);
+    }
+
+    #[test]
+    fn open_reads_from_checkpoint() {
+        let path = std::env::temp_dir().join(format!("olc_utils_wal_open_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let wal = Wal::open(&path).unwrap();
+        let update = wal.append(&records()[0]).unwrap();
+        let checkpoint = wal.end_checkpoint(&wal.begin_checkpoint()).unwrap();
+        let commit = wal.commit(1).unwrap();
+        let end = wal.flushed_lsn();
+        drop(wal);
+        // the record before the checkpoint is not decoded, so corrupting it does not cut off the log there
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[0xff], update.x + RECORD_HEADER_SIZE as u64).unwrap();
+        let wal = Wal::open(&path).unwrap();
+        assert_eq!(wal.flushed_lsn(), end);
+        assert_eq!(wal.last_checkpoint(), Some(checkpoint));
+        assert_eq!(wal.records_from(commit).unwrap(), vec![(commit, LogRecord::Commit { txn: 1 })]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_tail() {
+        for record in records() {
+            let mut bytes = Vec::new();
+            LogRecord::Commit { txn: 1 }.encode(&mut bytes);
+            let first = bytes.len();
+            record.encode(&mut bytes);
+            for len in first..bytes.len() {
+                assert_eq!(LogRecord::decode(&bytes[first..len]), None);
+                let (decoded, end) = decode_all(&bytes[..len], 0);
+                assert_eq!(decoded, vec![(Lsn { x: 0 }, LogRecord::Commit { txn: 1 })]);
+                assert_eq!(end, first as u64);
+            }
+            // a partially written record may have garbage in place of bytes that were not written
+            for i in first..bytes.len() {
+                let mut corrupt = bytes.clone();
+                corrupt[i] ^= 0x40;
+                assert_eq!(decode_all(&corrupt, 0).1, first as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn short_length() {
+        let mut bytes = Vec::new();
+        LogRecord::Commit { txn: 1 }.encode(&mut bytes);
+        let first = bytes.len();
+        LogRecord::Commit { txn: 2 }.encode(&mut bytes);
+        for len in 0..RECORD_HEADER_SIZE as u32 {
+            // the checksum matches, only the length shows the record is corrupt
+            let mut corrupt = bytes.clone();
+            corrupt[first..][..4].copy_from_slice(&len.to_ne_bytes());
+            let sum = checksum(&corrupt[first..][..8], &[]);
+            corrupt[first + 8..][..8].copy_from_slice(&sum.to_ne_bytes());
+            assert_eq!(LogRecord::decode(&corrupt[first..]), None);
+            assert_eq!(decode_all(&corrupt, 0).1, first as u64);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::temp_path;
+use olc_utils::*;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Page {
+    data: [u64; 512],
+}
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Small {
+    data: [u64; 16],
+}
+
+fn single<'bm, BM: BufferManager<'bm>>(bm: BM)
+where
+    BM::Page: bytemuck::Pod,
+{
+    let t = BTree::<BM, u64, u64>::new(bm).unwrap();
+    let mut m = BTreeMap::new();
+    let mut x = 12345u64;
+    for i in 0..20000u64 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let k = (x >> 33) % 5000;
+        match i % 4 {
+            0 | 1 => assert_eq!(t.insert(k, i).unwrap(), m.insert(k, i)),
+            2 => assert_eq!(t.remove(k), m.remove(&k)),
+            _ => assert_eq!(t.lookup(k), m.get(&k).copied()),
+        }
+        if i % 997 == 0 {
+            let mut v = Vec::new();
+            t.scan(k, |k, val| {
+                v.push((k, val));
+                v.len() < 100
+            });
+            let e: Vec<_> = m.range(k..).take(100).map(|(a, b)| (*a, *b)).collect();
+            assert_eq!(v, e);
+        }
+    }
+    let mut v = Vec::new();
+    t.scan(0, |k, val| {
+        v.push((k, val));
+        true
+    });
+    assert_eq!(v, m.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn single_threaded() {
+    single(&SimpleBm::<Page>::new(100));
+    single(&SimpleBm::<Small>::new(10000));
+    single(&SimpleBm::<Small, HybridLock>::new(10000));
+}
+
+#[test]
+fn concurrent() {
+    let bm = SimpleBm::<Small>::new(100000);
+    let t = BTree::<_, u64, u64>::new(&bm).unwrap();
+    let t = &t;
+    std::thread::scope(|s| {
+        for th in 0..8u64 {
+            s.spawn(move || {
+                for i in 0..5000u64 {
+                    let k = i * 8 + th;
+                    assert_eq!(t.insert(k, k * 2).unwrap(), None);
+                    assert_eq!(t.lookup(k), Some(k * 2));
+                    if i % 3 == 0 {
+                        assert_eq!(t.remove(k), Some(k * 2));
+                    }
+                    if i % 100 == 0 {
+                        let mut last = None;
+                        t.scan(i, |k, v| {
+                            assert_eq!(v, k * 2);
+                            assert!(last < Some(k));
+                            last = Some(k);
+                            true
+                        });
+                    }
+                }
+            });
+        }
+    });
+    let mut n = 0;
+    t.scan(0, |k, v| {
+        assert_eq!(v, k * 2);
+        assert!((k / 8) % 3 != 0);
+        n += 1;
+        true
+    });
+    assert_eq!(n, 8 * (5000 - 1667));
+    let t2 = BTree::<_, u64, u64>::open(&bm, t.meta_page_id());
+    assert_eq!(t2.lookup(8), Some(16));
+}
+
+#[test]
+fn out_of_pages() {
+    let bm = SimpleBm::<Small>::new(8);
+    assert!(BTree::<_, u64, u64>::new(&SimpleBm::<Small>::new(1)).is_err());
+    let t = BTree::<_, u64, u64>::new(&bm).unwrap();
+    let mut inserted = 0;
+    while t.insert(inserted, inserted * 2).is_ok() {
+        inserted += 1;
+    }
+    assert!(inserted > 0);
+    for k in 0..inserted {
+        assert_eq!(t.lookup(k), Some(k * 2));
+    }
+    assert_eq!(t.lookup(inserted), None);
+    assert!(t.insert(inserted, 0).is_err());
+    assert_eq!(t.remove(1), Some(2));
+    let mut v = Vec::new();
+    t.scan(0, |k, _| {
+        v.push(k);
+        true
+    });
+    assert_eq!(v, (0..inserted).filter(|&k| k != 1).collect::<Vec<_>>());
+}
+
+#[test]
+fn disk() {
+    let path = temp_path("btree");
+    let meta;
+    {
+        let bm = DiskBm::<Page>::open(&path, 8, 10000).unwrap();
+        single(&bm);
+        let t = BTree::<_, u64, u64>::new(&bm).unwrap();
+        t.insert(7, 14).unwrap();
+        meta = t.meta_page_id();
+    }
+    let bm = DiskBm::<Page>::open(&path, 8, 10000).unwrap();
+    assert_eq!(BTree::<_, u64, u64>::open(&bm, meta).lookup(7), Some(14));
+    std::fs::remove_file(&path).unwrap();
+}
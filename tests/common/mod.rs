@@ -0,0 +1,36 @@
+//! Helpers shared by the integration tests, each test binary uses some of them.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::thread::Scope;
+
+/// A path in the temporary directory that is unique to this process, with any file left there removed.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("olc_utils_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Runs `f` on a new thread and waits for it.
+/// Locks taken while the calling thread holds the same lock would trip `track-thread-locks`.
+pub fn on_other_thread<R: Send>(f: impl FnOnce() -> R + Send) -> R {
+    std::thread::scope(|s| s.spawn(f).join().unwrap())
+}
+
+/// Holds the guard returned by `acquire` in another thread until the returned function is called,
+/// which waits until the guard is dropped.
+pub fn hold<'s, G>(s: &'s Scope<'s, '_>, acquire: impl FnOnce() -> G + Send + 's) -> impl FnOnce() + Send + 's {
+    let (release, released) = std::sync::mpsc::channel::<()>();
+    let (locked, is_locked) = std::sync::mpsc::channel();
+    let t = s.spawn(move || {
+        let g = acquire();
+        locked.send(()).unwrap();
+        let _ = released.recv();
+        drop(g);
+    });
+    is_locked.recv().unwrap();
+    move || {
+        drop(release);
+        t.join().unwrap();
+    }
+}
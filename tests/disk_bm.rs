@@ -0,0 +1,169 @@
+mod common;
+
+use common::temp_path;
+use olc_utils::*;
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Page {
+    data: [u64; 64],
+}
+
+#[test]
+fn evict_and_reopen() {
+    let path = temp_path("disk_bm_evict");
+    let pids: Vec<PageId>;
+    {
+        let bm = DiskBm::<Page>::open(&path, 4, 1000).unwrap();
+        let bm = &bm;
+        pids = (0..100u64)
+            .map(|i| {
+                let mut g = BufferManager::alloc(bm);
+                g.data[0] = i;
+                g.data[63] = i * 2;
+                g.page_id()
+            })
+            .collect();
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let pids = &pids;
+                s.spawn(move || {
+                    for r in 0..2000usize {
+                        let pid = pids[(r * 7 + t * 13) % 100];
+                        match r % 3 {
+                            0 => bm.lock_exclusive(pid).data[1] += 1,
+                            1 => {
+                                let g = bm.lock_shared(pid);
+                                assert_eq!(g.data[63], g.data[0] * 2);
+                            }
+                            _ => {
+                                let (x, y) = <&DiskBm<Page>>::repeat(|| {
+                                    let g = bm.lock_optimistic(pid);
+                                    let data = g.o_ptr_bm().as_slice::<u64>();
+                                    let xy = (data.i(0).r(), data.i(63).r());
+                                    g.check();
+                                    xy
+                                });
+                                assert_eq!(y, x * 2);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+    let bm = DiskBm::<Page>::open(&path, 4, 1000).unwrap();
+    let mut total = 0;
+    for (i, &pid) in pids.iter().enumerate() {
+        let g = (&bm).lock_shared(pid);
+        assert_eq!(g.data[0], i as u64);
+        total += g.data[1];
+    }
+    assert_eq!(total, 4 * 667);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn try_lock_loads_evicted_page() {
+    let path = temp_path("disk_bm_try_lock");
+    let bm = DiskBm::<Page>::open(&path, 2, 100).unwrap();
+    let bm = &bm;
+    let pids: Vec<PageId> = (0..8u64)
+        .map(|i| {
+            let mut g = BufferManager::alloc(bm);
+            g.data[0] = i;
+            g.page_id()
+        })
+        .collect();
+    assert_eq!(bm.try_lock_shared(pids[0]).unwrap().data[0], 0);
+    bm.try_lock_exclusive(pids[1]).unwrap().data[0] = 10;
+    assert_eq!(bm.lock_shared_timeout(pids[2], std::time::Duration::ZERO).unwrap().data[0], 2);
+    let g = bm.lock_shared(pids[1]);
+    assert_eq!(g.data[0], 10);
+    assert_eq!(CommonSeqLockBM::pid_from_address(bm, (&*g as *const Page).addr()), pids[1]);
+    drop(g);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn alloc_with_pinned_pool() {
+    let path = temp_path("disk_bm_pinned");
+    let bm = DiskBm::<Page>::open(&path, 2, 3).unwrap();
+    let bm = &bm;
+    let a = BufferManager::alloc(bm);
+    let b = BufferManager::alloc(bm);
+    // the file has room for another page, but no frame can be evicted
+    assert_eq!(BufferManager::try_alloc(bm).err(), Some(AllocError::PoolExhausted));
+    drop(a);
+    let c = BufferManager::alloc(bm);
+    assert_eq!(BufferManager::try_alloc(bm).err(), Some(AllocError::OutOfPages));
+    drop((b, c));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn flush_page_clears_dirty() {
+    let path = temp_path("disk_bm_flush_page");
+    let bm = DiskBm::<Page>::open(&path, 4, 10).unwrap();
+    let pids: Vec<PageId> = (0..2).map(|_| BufferManager::alloc(&bm).page_id()).collect();
+    (&bm).lock_exclusive(pids[1]).data[0] = 7;
+    assert_eq!(bm.dirty_pages().len(), 2);
+    assert!(bm.flush_page(pids[1]).unwrap());
+    assert!(!bm.flush_page(pids[1]).unwrap());
+    assert_eq!(bm.dirty_pages(), vec![pids[0]]);
+    assert!(bm.flush_page(pids[0]).unwrap());
+    assert!(bm.dirty_pages().is_empty());
+    drop(bm);
+    let bm = DiskBm::<Page>::open(&path, 4, 10).unwrap();
+    assert_eq!((&bm).lock_shared(pids[1]).data[0], 7);
+    drop(bm);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reuse_page_loaded_after_dealloc() {
+    let path = temp_path("disk_bm_stale_reader");
+    let bm = DiskBm::<Page>::open(&path, 2, 100).unwrap();
+    let bm = &bm;
+    let a = BufferManager::alloc(bm).page_id();
+    bm.lock_exclusive(a).dealloc();
+    // a reader with a stale page id loads the freed page into a frame
+    drop(bm.lock_optimistic(a));
+    let g = BufferManager::alloc(bm);
+    assert_eq!(g.page_id(), a);
+    // the reused page took over the frame of the stale load, so the other frame is still available
+    let b = BufferManager::try_alloc(bm).unwrap();
+    drop((g, b));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn load_with_pinned_pool() {
+    let path = temp_path("disk_bm_pinned_load");
+    let bm = DiskBm::<Page>::open(&path, 2, 10).unwrap();
+    let bm = &bm;
+    let pids: Vec<PageId> = (0..3u64)
+        .map(|i| {
+            let mut g = BufferManager::alloc(bm);
+            g.data[0] = i;
+            g.page_id()
+        })
+        .collect();
+    let (evicted, resident): (Vec<PageId>, Vec<PageId>) =
+        pids.iter().partition(|&&pid| !CommonSeqLockBM::is_resident(bm, pid));
+    let (evicted, b) = (evicted[0], bm.lock_exclusive(resident[0]));
+    let c = bm.lock_exclusive(resident[1]);
+    let timeout = std::time::Duration::from_millis(10);
+    std::thread::scope(|s| {
+        assert!(s.spawn(|| bm.try_lock_shared(evicted).is_none()).join().unwrap());
+        assert!(s.spawn(|| bm.try_lock_optimistic(evicted).is_none()).join().unwrap());
+        assert!(s.spawn(|| bm.lock_exclusive_timeout(evicted, timeout).is_err()).join().unwrap());
+        // a blocking acquire waits until a resident page is unlocked
+        let waiting = s.spawn(|| bm.lock_shared(evicted).data[0]);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(b);
+        assert_eq!(waiting.join().unwrap(), evicted.x);
+    });
+    drop(c);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,92 @@
+use olc_utils::*;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Small {
+    data: [u64; 16],
+}
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Page {
+    data: [u64; 512],
+}
+
+fn single<'bm, BM: BufferManager<'bm>>(bm: BM)
+where
+    BM::Page: bytemuck::Pod,
+{
+    let t = HashTable::<BM, u64, u64>::new(bm).unwrap();
+    let mut m = HashMap::new();
+    let mut x = 99u64;
+    for i in 0..20000u64 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let k = (x >> 33) % 3000;
+        match i % 4 {
+            0 | 1 => assert_eq!(t.insert(k, i).unwrap(), m.insert(k, i)),
+            2 => assert_eq!(t.remove(k), m.remove(&k)),
+            _ => assert_eq!(t.lookup(k), m.get(&k).copied()),
+        }
+    }
+    for k in 0..3000 {
+        assert_eq!(t.lookup(k), m.get(&k).copied());
+    }
+}
+
+#[test]
+fn single_threaded() {
+    single(&SimpleBm::<Small>::new(10000));
+    single(&SimpleBm::<Page>::new(1000));
+    single(&SimpleBm::<Small, HybridLock>::new(10000));
+}
+
+#[test]
+fn concurrent() {
+    let bm = SimpleBm::<Small>::new(100000);
+    let t = HashTable::<_, u64, u64>::new(&bm).unwrap();
+    let t = &t;
+    std::thread::scope(|s| {
+        for th in 0..8u64 {
+            s.spawn(move || {
+                for i in 0..5000u64 {
+                    let k = i * 8 + th;
+                    assert_eq!(t.insert(k, k * 2).unwrap(), None);
+                    assert_eq!(t.lookup(k), Some(k * 2));
+                    if i % 3 == 0 {
+                        assert_eq!(t.remove(k), Some(k * 2));
+                    }
+                    let j = (i * 7 + 3) % 40000;
+                    if let Some(v) = t.lookup(j) {
+                        assert_eq!(v, j * 2);
+                    }
+                }
+            });
+        }
+    });
+    for k in 0..40000u64 {
+        assert_eq!(t.lookup(k), if (k / 8) % 3 != 0 { Some(k * 2) } else { None });
+    }
+    let t2 = HashTable::<_, u64, u64>::open(&bm, t.directory_page_id());
+    assert_eq!(t2.lookup(8), Some(16));
+}
+
+#[test]
+fn out_of_pages() {
+    let bm = SimpleBm::<Small>::new(8);
+    assert!(HashTable::<_, u64, u64>::new(&SimpleBm::<Small>::new(1)).is_err());
+    let t = HashTable::<_, u64, u64>::new(&bm).unwrap();
+    let mut inserted = 0;
+    while t.insert(inserted, inserted * 2).is_ok() {
+        inserted += 1;
+    }
+    assert!(inserted > 0);
+    for k in 0..inserted {
+        assert_eq!(t.lookup(k), Some(k * 2));
+    }
+    assert_eq!(t.lookup(inserted), None);
+    assert!(t.insert(inserted, 0).is_err());
+    assert_eq!(t.insert(0, 1), Ok(Some(0)));
+    assert_eq!(t.remove(1), Some(2));
+    assert_eq!(t.insert(1, 2), Ok(None));
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::on_other_thread;
+use olc_utils::*;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+fn guard_modes<L: PageLock>() {
+    let l = L::zeroed();
+    let v = OptimisticRead::new(&l).version().x;
+    let g = SeqLockGuard::shared(&l);
+    assert!(!g.is_exclusive());
+    // shared guards leave the version unchanged
+    assert_eq!(g.release().x, v);
+    let read = OptimisticRead::new(&l);
+    let mut g = SeqLockGuard::exclusive(&l);
+    assert!(g.is_exclusive());
+    g.downgrade();
+    assert!(!g.is_exclusive());
+    assert!(read.check().is_err());
+    assert!(g.try_upgrade());
+    assert!(g.is_exclusive());
+    // both the downgrade and the release publish a new version
+    assert_eq!(g.release().x, v + 2);
+    drop(SeqLockGuard::exclusive(&l));
+    assert_eq!(OptimisticRead::new(&l).version().x, v + 3);
+    drop(SeqLockGuard::shared(&l));
+    // dropped guards left the lock free
+    drop(SeqLockGuard::try_exclusive(&l).unwrap());
+}
+
+#[test]
+fn seq_lock_guard() {
+    guard_modes::<SeqLock>();
+    guard_modes::<HybridLock>();
+}
+
+fn optimistic_reads<L: PageLock>() {
+    let l = L::zeroed();
+    let read = OptimisticRead::new(&l);
+    assert!(read.check().is_ok());
+    drop(SeqLockGuard::shared(&l));
+    assert!(read.check().is_ok());
+    let Ok(g) = read.upgrade_shared() else { panic!("no write since the read started") };
+    assert!(!g.is_exclusive());
+    drop(g);
+    // the read is a copy, upgrading it again validates against the same version
+    let Ok(g) = read.upgrade_exclusive() else { panic!("no write since the read started") };
+    assert!(g.is_exclusive());
+    drop(g);
+    assert!(read.check().is_err());
+    assert!(read.upgrade_shared().is_err());
+    assert!(read.upgrade_exclusive().is_err());
+    // failed upgrades leave the lock free
+    drop(SeqLockGuard::try_exclusive(&l).unwrap());
+    let read = OptimisticRead::try_new(&l).unwrap();
+    assert_eq!(read.version().x, OptimisticRead::new(&l).version().x);
+}
+
+#[test]
+fn optimistic_read() {
+    optimistic_reads::<SeqLock>();
+    optimistic_reads::<HybridLock>();
+}
+
+#[test]
+fn exclusive_guard_excludes_writers() {
+    let l = SeqLock::new();
+    let counter = AtomicU64::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10_000 {
+                    let _g = SeqLockGuard::<SeqLock>::exclusive(&l);
+                    // a second writer inside would make this non-atomic update lose increments
+                    counter.store(counter.load(Relaxed) + 1, Relaxed);
+                }
+            });
+        }
+    });
+    assert_eq!(counter.into_inner(), 40_000);
+    assert_eq!(OptimisticRead::<SeqLock>::new(&l).version().x, 40_000);
+}
+
+fn try_while_held<L: PageLock>() {
+    let l = L::zeroed();
+    let g = SeqLockGuard::exclusive(&l);
+    assert!(on_other_thread(|| SeqLockGuard::try_shared(&l).is_none()));
+    assert!(on_other_thread(|| SeqLockGuard::try_exclusive(&l).is_none()));
+    assert!(on_other_thread(|| OptimisticRead::try_new(&l).is_none()));
+    assert!(on_other_thread(|| l.try_lock_shared(()).is_none()));
+    drop(g);
+    let g = SeqLockGuard::shared(&l);
+    assert!(on_other_thread(|| SeqLockGuard::try_shared(&l).is_some()));
+    assert!(on_other_thread(|| SeqLockGuard::try_exclusive(&l).is_none()));
+    assert!(on_other_thread(|| OptimisticRead::try_new(&l).is_some()));
+    assert!(on_other_thread(|| l.lock_exclusive_timeout(std::time::Duration::from_millis(10)).is_err()));
+    drop(g);
+    // nothing was left locked by the failed attempts
+    let v = OptimisticRead::new(&l).version();
+    drop(SeqLockGuard::try_exclusive(&l).unwrap());
+    assert_eq!(OptimisticRead::new(&l).version().x, v.x + 1);
+}
+
+#[test]
+fn try_acquire_while_held() {
+    try_while_held::<SeqLock>();
+    try_while_held::<HybridLock>();
+    try_while_held::<SeqLock<Fair<ParkWait>>>();
+}
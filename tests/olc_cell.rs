@@ -0,0 +1,90 @@
+use olc_utils::*;
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod, Default)]
+#[repr(C)]
+struct Cfg {
+    a: u64,
+    b: u64,
+}
+
+fn concurrent<O: OlcErrorHandler>() {
+    let c: OlcCell<Cfg, O> = OlcCell::new(Cfg { a: 1, b: 2 });
+    let c = &c;
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            s.spawn(move || {
+                for i in 0..20000u64 {
+                    if (i + t) % 10 == 0 {
+                        c.write(|v| {
+                            v.a += 1;
+                            v.b = v.a * 2;
+                        });
+                    } else {
+                        let v = c.load();
+                        assert_eq!(v.b, v.a * 2);
+                        let a = c.read(|p| o_project!(p.a).r());
+                        assert!(a >= 1);
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(c.load().a, 1 + 8000);
+}
+
+#[test]
+fn read_while_writing() {
+    concurrent::<UnwindOlcEh>();
+}
+
+#[test]
+fn read_with_panic_handler() {
+    concurrent::<PanicOlcEh>();
+}
+
+#[test]
+fn read_retries_failures() {
+    let c: OlcCell<Cfg> = OlcCell::new(Cfg { a: 1, b: 2 });
+    let mut first = true;
+    let a = c.read(|p| {
+        if std::mem::take(&mut first) {
+            UnwindOlcEh::optimistic_fail();
+        }
+        o_project!(p.a).r()
+    });
+    assert_eq!(a, 1);
+    let c = &c;
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for _ in 0..20000 {
+                c.write(|v| {
+                    v.a += 1;
+                    v.b = v.a * 2;
+                });
+            }
+        });
+        s.spawn(move || {
+            for _ in 0..20000 {
+                let (a, b) = c.read(|p| {
+                    let (a, b) = (o_project!(p.a).r(), o_project!(p.b).r());
+                    // torn between the writes to a and b
+                    if b != a * 2 {
+                        UnwindOlcEh::optimistic_fail();
+                    }
+                    (a, b)
+                });
+                assert_eq!(b, a * 2);
+            }
+        });
+    });
+}
+
+#[test]
+fn store_and_into_inner() {
+    let c: OlcCell<Cfg> = OlcCell::new(Cfg { a: 1, b: 2 });
+    c.store(Cfg::default());
+    assert_eq!(c.load().b, 0);
+    let mut c: OlcCell<Cfg> = OlcCell::default();
+    c.get_mut().a = 5;
+    assert_eq!(c.into_inner().a, 5);
+}
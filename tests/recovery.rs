@@ -0,0 +1,335 @@
+//! Kills child processes running logged transactions and checks that recovery restores a consistent state.
+//! The children are this test binary running `recovery_child`, which does nothing unless started by a test.
+//! Tests that only need to look at the log run in process.
+
+mod common;
+
+use common::temp_path;
+use olc_utils::*;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Page {
+    data: [u64; 64],
+}
+
+const THREADS: u64 = 4;
+/// pages owned by each thread
+const THREAD_PAGES: u64 = 10;
+/// updates per transaction
+const TXN_UPDATES: u64 = 3;
+/// updates of the transaction left uncommitted in `uncommitted` mode
+const LONG_TXN_UPDATES: u64 = 20000;
+/// the page freed and allocated again in `reuse` mode, the first one after those of the threads
+const REUSED_PAGE: PageId = PageId { x: THREADS * THREAD_PAGES };
+
+fn open(dir: &Path) -> DiskBm<Page> {
+    DiskBm::open_with_wal(dir.join("pages"), dir.join("log"), 8, 1000).unwrap()
+}
+
+/// Transactions of thread `t` increment `data[1]` on its own pages, so the sum over them counts its updates.
+fn txn_id(t: u64, i: u64) -> u64 {
+    ((std::process::id() as u64) << 32) | (t << 24) | i
+}
+
+fn txn_thread(txn: u64) -> u64 {
+    (txn >> 24) & 0xff
+}
+
+fn increment(bm: &DiskBm<Page>, txn: u64, pid: PageId) {
+    let mut g = bm.lock_exclusive(pid);
+    let v = g.data[1] + 1;
+    g.write_logged(txn, 8, bytemuck::bytes_of(&v)).unwrap();
+}
+
+#[test]
+fn recovery_child() {
+    let (Ok(dir), Ok(mode)) = (std::env::var("OLC_RECOVERY_DIR"), std::env::var("OLC_RECOVERY_MODE")) else {
+        return;
+    };
+    let bm = open(Path::new(&dir));
+    bm.recover().unwrap();
+    let bm = &bm;
+    match mode.as_str() {
+        "recover" => (),
+        "uncommitted" => {
+            std::thread::scope(|s| {
+                for t in 0..THREADS {
+                    s.spawn(move || {
+                        let txn = txn_id(t, 0xffffff);
+                        for i in 0..LONG_TXN_UPDATES {
+                            increment(bm, txn, PageId { x: t * THREAD_PAGES + i % THREAD_PAGES });
+                        }
+                    });
+                }
+            });
+            bm.wal().unwrap().flush_all().unwrap();
+            ready();
+        }
+        "reuse" => {
+            let txn = txn_id(0, 0);
+            let mut g = BufferManager::alloc(bm);
+            assert_eq!(g.page_id(), REUSED_PAGE);
+            g.write_logged(txn, 0, bytemuck::bytes_of(&1u64)).unwrap();
+            drop(g);
+            bm.wal().unwrap().commit(txn).unwrap();
+            bm.lock_exclusive(REUSED_PAGE).dealloc();
+            assert_eq!(BufferManager::alloc(bm).page_id(), REUSED_PAGE);
+            bm.flush().unwrap();
+            ready();
+        }
+        "reused_txn" => {
+            // the same id for a committed transaction and one that is still running at the crash
+            let txn = txn_id(0, 0);
+            increment(bm, txn, PageId { x: 0 });
+            bm.wal().unwrap().commit(txn).unwrap();
+            increment(bm, txn, PageId { x: 1 });
+            bm.wal().unwrap().flush_all().unwrap();
+            bm.flush().unwrap();
+            ready();
+        }
+        "run" | "run_checkpointed" => std::thread::scope(|s| {
+            if mode == "run_checkpointed" {
+                s.spawn(move || loop {
+                    std::thread::sleep(Duration::from_millis(20));
+                    bm.checkpoint().unwrap();
+                });
+            }
+            for t in 0..THREADS {
+                s.spawn(move || {
+                    for i in 0u64.. {
+                        let txn = txn_id(t, i);
+                        for k in 0..TXN_UPDATES {
+                            increment(bm, txn, PageId { x: t * THREAD_PAGES + (i * TXN_UPDATES + k) % THREAD_PAGES });
+                            if i % 7 == 0 {
+                                std::thread::sleep(Duration::from_micros(50));
+                            }
+                        }
+                        bm.wal().unwrap().commit(txn).unwrap();
+                    }
+                });
+            }
+        }),
+        _ => panic!("unknown mode {mode}"),
+    }
+}
+
+/// Tells the parent that the child is ready to be killed.
+fn ready() -> ! {
+    println!("ready");
+    loop {
+        std::thread::park();
+    }
+}
+
+fn spawn(dir: &Path, mode: &str) -> Child {
+    Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "recovery_child", "--nocapture", "--test-threads=1"])
+        .env("OLC_RECOVERY_DIR", dir)
+        .env("OLC_RECOVERY_MODE", mode)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap()
+}
+
+fn wait_ready(child: &mut Child) {
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    // the harness prints the test name on the same line before running it
+    assert!(lines.any(|line| line.unwrap().ends_with("ready")));
+}
+
+fn kill(mut child: Child) {
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+fn setup(name: &str) -> PathBuf {
+    let dir = temp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let bm = open(&dir);
+    for _ in 0..THREADS * THREAD_PAGES {
+        BufferManager::alloc(&bm);
+    }
+    dir
+}
+
+/// Recovers and checks that exactly the updates of committed transactions are on the pages.
+/// Returns the number of committed transactions.
+fn check(dir: &Path) -> u64 {
+    let bm = open(dir);
+    bm.recover().unwrap();
+    let wal = bm.wal().unwrap();
+    let records = wal.records().unwrap();
+    if let Some(checkpoint) = wal.last_checkpoint() {
+        assert!(matches!(records.iter().find(|r| r.0 == checkpoint).unwrap().1, LogRecord::Checkpoint { .. }));
+    }
+    let mut ended = HashSet::new();
+    let mut commits = [0; THREADS as usize];
+    for (_, record) in &records {
+        match record {
+            LogRecord::Commit { txn } => {
+                commits[txn_thread(*txn) as usize] += 1;
+                assert!(ended.insert(*txn));
+            }
+            LogRecord::Abort { txn } => assert!(ended.insert(*txn)),
+            _ => (),
+        }
+    }
+    // recovery ended all transactions
+    for (_, record) in &records {
+        if let LogRecord::Update { txn, .. } = record {
+            assert!(ended.contains(txn));
+        }
+    }
+    for t in 0..THREADS {
+        let sum: u64 = (t * THREAD_PAGES..(t + 1) * THREAD_PAGES).map(|x| bm.lock_shared(PageId { x }).data[1]).sum();
+        assert_eq!(sum, commits[t as usize] * TXN_UPDATES, "thread {t}");
+    }
+    commits.iter().sum()
+}
+
+fn run(name: &str, mode: &str) -> PathBuf {
+    let dir = setup(name);
+    let mut last = 0;
+    for round in 0..4 {
+        let child = spawn(&dir, mode);
+        std::thread::sleep(Duration::from_millis(300 + round * 100));
+        kill(child);
+        let commits = check(&dir);
+        assert!(commits > last, "no progress in round {round}");
+        last = commits;
+        // recovering again changes nothing
+        assert_eq!(check(&dir), commits);
+    }
+    dir
+}
+
+#[test]
+fn recovery_after_kill() {
+    let dir = run("recovery", "run");
+    let records = Wal::open(dir.join("log")).unwrap().records().unwrap();
+    assert!(records.iter().any(|r| matches!(r.1, LogRecord::Compensation { .. })));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recovery_after_kill_checkpointed() {
+    let dir = run("recovery_checkpointed", "run_checkpointed");
+    assert!(Wal::open(dir.join("log")).unwrap().last_checkpoint().is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Kills recovery once some of its compensation records are durable, so the next recovery continues undoing.
+#[test]
+fn recovery_after_kill_during_recovery() {
+    let dir = run("recovery_interrupted", "run");
+    let commits = check(&dir);
+    let mut child = spawn(&dir, "uncommitted");
+    wait_ready(&mut child);
+    kill(child);
+    let log = dir.join("log");
+    let log_len = std::fs::metadata(&log).unwrap().len();
+    let child = spawn(&dir, "recover");
+    while std::fs::metadata(&log).unwrap().len() == log_len {
+        std::thread::sleep(Duration::from_micros(100));
+    }
+    kill(child);
+    let records = Wal::open(&log).unwrap().records().unwrap();
+    let aborted: HashSet<u64> = records
+        .iter()
+        .filter_map(|r| match r.1 {
+            LogRecord::Abort { txn } => Some(txn),
+            _ => None,
+        })
+        .collect();
+    let interrupted = records.iter().any(|r| match r.1 {
+        LogRecord::Compensation { txn, .. } => r.0.x >= log_len && !aborted.contains(&txn),
+        _ => false,
+    });
+    assert!(interrupted, "recovery completed before it was killed");
+    assert_eq!(check(&dir), commits);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Frees a page with a committed update and allocates it again, which zeroes it, then writes it back.
+#[test]
+fn recovery_after_reuse() {
+    let dir = setup("recovery_reuse");
+    let mut child = spawn(&dir, "reuse");
+    wait_ready(&mut child);
+    kill(child);
+    let bm = open(&dir);
+    bm.recover().unwrap();
+    // the update was made before the page was freed, so it must not be redone
+    assert_eq!(bm.lock_shared(REUSED_PAGE).data[0], 0);
+    drop(bm);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn recovery_with_reused_txn_id() {
+    let dir = setup("recovery_reused_txn");
+    let mut child = spawn(&dir, "reused_txn");
+    wait_ready(&mut child);
+    kill(child);
+    let bm = open(&dir);
+    bm.recover().unwrap();
+    // only the update after the commit is undone
+    assert_eq!(bm.lock_shared(PageId { x: 0 }).data[1], 1);
+    assert_eq!(bm.lock_shared(PageId { x: 1 }).data[1], 0);
+    drop(bm);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Freeing a page drops it from the dirty pages, so later checkpoints do not keep redo at its changes.
+#[test]
+fn checkpoint_after_dealloc() {
+    let dir = setup("checkpoint_dealloc");
+    let bm = open(&dir);
+    bm.recover().unwrap();
+    let txn = txn_id(0, 0);
+    let mut g = BufferManager::alloc(&bm);
+    let pid = g.page_id();
+    let update = g.write_logged(txn, 0, bytemuck::bytes_of(&1u64)).unwrap();
+    drop(g);
+    bm.wal().unwrap().commit(txn).unwrap();
+    bm.lock_exclusive(pid).dealloc();
+    let checkpoint = bm.checkpoint().unwrap();
+    let records = bm.wal().unwrap().records_from(checkpoint).unwrap();
+    let LogRecord::Checkpoint { begin, dirty_pages, active_txns } = &records[0].1 else {
+        panic!("not a checkpoint");
+    };
+    assert!(active_txns.is_empty());
+    let redo_start = dirty_pages.iter().map(|&(_, lsn)| lsn).fold(*begin, Lsn::min);
+    assert!(redo_start > update);
+    drop(bm);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn write_logged_out_of_range() {
+    let dir = setup("write_logged_range");
+    let bm = open(&dir);
+    bm.recover().unwrap();
+    bm.flush().unwrap();
+    let end = bm.wal().unwrap().flushed_lsn();
+    let mut g = bm.lock_exclusive(PageId { x: 0 });
+    let page_size = std::mem::size_of::<Page>();
+    for (offset, len) in [(page_size - 4, 8), (page_size, 1), (usize::MAX, 1), (1 << 32, 0)] {
+        let e = g.write_logged(1, offset, &vec![1; len]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    }
+    drop(g);
+    // nothing was logged or written
+    assert_eq!(bm.wal().unwrap().flushed_lsn(), end);
+    assert!(bm.wal().unwrap().records().unwrap().is_empty());
+    assert!(bm.dirty_pages().is_empty());
+    drop(bm);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
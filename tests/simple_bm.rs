@@ -0,0 +1,254 @@
+mod common;
+
+use common::{hold, on_other_thread};
+use olc_utils::*;
+
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Page {
+    data: [u64; 8],
+}
+
+#[test]
+fn grow() {
+    let bm = SimpleBm::<Page>::growable(3);
+    let bm = &bm;
+    let mut pids = Vec::new();
+    for i in 0..1000u64 {
+        let mut g = BufferManager::try_alloc(bm).unwrap();
+        g.data[0] = i;
+        pids.push(g.page_id());
+    }
+    for (i, &pid) in pids.iter().enumerate() {
+        let g = bm.lock_shared(pid);
+        assert_eq!(g.data[0], i as u64);
+        assert_eq!(g.page_id(), pid);
+        // most pages were allocated after the pool grew
+        assert_eq!(CommonSeqLockBM::pid_from_address(bm, (&*g as *const Page).addr()), pid);
+    }
+}
+
+#[test]
+fn fixed_capacity() {
+    let bm = SimpleBm::<Page>::new(2);
+    let bm = &bm;
+    let a = BufferManager::try_alloc(bm).unwrap();
+    let b = BufferManager::try_alloc(bm).unwrap();
+    assert_eq!(BufferManager::try_alloc(bm).err(), Some(AllocError::OutOfPages));
+    a.dealloc();
+    assert!(BufferManager::try_alloc(bm).is_ok());
+    drop(b);
+}
+
+#[test]
+#[should_panic(expected = "page id out of range")]
+fn page_id_out_of_range() {
+    let bm = SimpleBm::<Page>::new(10);
+    (&bm).lock_shared(PageId { x: 1000 });
+}
+
+#[test]
+#[should_panic(expected = "page id out of range")]
+fn page_id_beyond_segments() {
+    let bm = SimpleBm::<Page>::growable(10);
+    (&bm).lock_shared(PageId { x: u64::MAX });
+}
+
+#[test]
+fn alloc_never_fails_while_pages_are_free() {
+    let threads = 8;
+    let bm = SimpleBm::<Page>::new(threads);
+    let bm = &bm;
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            // each thread holds at most one page, so there is always a free one
+            s.spawn(move || {
+                for _ in 0..20_000 {
+                    BufferManager::try_alloc(bm).unwrap().dealloc();
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn dirty_tracking() {
+    let bm = SimpleBm::<Page>::new(4);
+    let bm = &bm;
+    let a = BufferManager::try_alloc(bm).unwrap().page_id();
+    let b = BufferManager::try_alloc(bm).unwrap().page_id();
+    assert!(bm.dirty_pages().is_empty());
+    // exclusive guards that only read leave the page clean
+    assert_eq!(bm.lock_exclusive(a).data[0], 0);
+    assert!(bm.dirty_pages().is_empty());
+    bm.lock_exclusive(b).data[0] = 1;
+    assert_eq!(bm.dirty_pages(), vec![b]);
+    bm.lock_exclusive(a).data[0] = 1;
+    let mut dirty = bm.take_dirty_pages();
+    dirty.sort_by_key(|pid| pid.x);
+    let mut expected = vec![a, b];
+    expected.sort_by_key(|pid| pid.x);
+    assert_eq!(dirty, expected);
+    assert!(bm.dirty_pages().is_empty());
+    bm.lock_exclusive(a).data[1] = 1;
+    assert!(bm.clear_dirty(a));
+    assert!(!bm.clear_dirty(a));
+    assert!(bm.dirty_pages().is_empty());
+}
+
+type GuardO<'bm> = SimpleGuardO<'bm, &'bm SimpleBm<Page>>;
+type GuardS<'bm> = SimpleGuardS<'bm, &'bm SimpleBm<Page>>;
+
+#[test]
+fn downgrade_versions() {
+    let bm = SimpleBm::<Page>::new(2);
+    let bm = &bm;
+    let pid = BufferManager::try_alloc(bm).unwrap().page_id();
+    let v = bm.lock_optimistic(pid).check().x;
+    // exclusive to shared publishes the write, but keeps writers out
+    let mut x = bm.lock_exclusive(pid);
+    x.data[0] = 1;
+    let s: GuardS = x.downgrade();
+    assert_eq!(on_other_thread(|| bm.lock_optimistic(pid).check().x), v + 1);
+    assert!(on_other_thread(|| bm.try_lock_exclusive(pid).is_none()));
+    assert_eq!(s.data[0], 1);
+    assert_eq!(s.release().x, v + 1);
+    // exclusive to optimistic validates against the version after the write
+    let mut x = bm.lock_exclusive(pid);
+    x.data[0] = 2;
+    let o: GuardO = x.downgrade();
+    assert_eq!(o.check().x, v + 2);
+    assert_eq!(o.o_ptr_bm().as_slice::<u64>().i(0).r(), 2);
+    on_other_thread(|| bm.lock_exclusive(pid).data[0] = 3);
+    assert!(UnwindOlcEh::catch(|| o.check()).is_err());
+    o.release_unchecked();
+    // shared to optimistic keeps the version and lets writers in
+    let s = bm.lock_shared(pid);
+    let o: GuardO = s.downgrade();
+    assert_eq!(o.check().x, v + 3);
+    on_other_thread(|| bm.try_lock_exclusive(pid).unwrap().data[0] = 4);
+    assert!(UnwindOlcEh::catch(|| o.check()).is_err());
+    o.release_unchecked();
+}
+
+#[test]
+fn try_upgrade_with_other_reader() {
+    let bm = SimpleBm::<Page>::new(2);
+    let bm = &bm;
+    let pid = BufferManager::try_alloc(bm).unwrap().page_id();
+    std::thread::scope(|s| {
+        let release_other = hold(s, move || bm.lock_shared(pid));
+        let Err(g) = bm.lock_shared(pid).try_upgrade() else {
+            panic!("upgraded while another thread holds the page");
+        };
+        // the failed upgrade kept the shared lock
+        assert!(on_other_thread(|| bm.try_lock_exclusive(pid).is_none()));
+        release_other();
+        let Ok(mut g) = g.try_upgrade() else {
+            panic!("no other reader left");
+        };
+        g.data[0] = 1;
+    });
+    assert_eq!(bm.lock_shared(pid).data[0], 1);
+}
+
+#[test]
+fn upgrade_validated_after_write() {
+    let bm = SimpleBm::<Page>::new(2);
+    let bm = &bm;
+    let pid = BufferManager::try_alloc(bm).unwrap().page_id();
+    std::thread::scope(|s| {
+        let g = bm.lock_shared(pid);
+        let writer = s.spawn(move || bm.lock_exclusive(pid).data[0] = 1);
+        // once the writer is waiting, it gets the page as soon as it is released
+        while on_other_thread(|| bm.try_lock_optimistic(pid).is_some()) {
+            std::thread::yield_now();
+        }
+        assert!(g.upgrade_validated().is_none());
+        writer.join().unwrap();
+    });
+    // without a write in between, the upgrade waits for the other reader
+    std::thread::scope(|s| {
+        let g = bm.lock_shared(pid);
+        let release_other = hold(s, move || bm.lock_shared(pid));
+        s.spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            release_other();
+        });
+        let mut g = g.upgrade_validated().unwrap();
+        g.data[0] = 2;
+    });
+    assert_eq!(bm.lock_shared(pid).data[0], 2);
+}
+
+#[test]
+fn try_lock_while_held() {
+    let bm = SimpleBm::<Page>::new(2);
+    let bm = &bm;
+    let pid = BufferManager::try_alloc(bm).unwrap().page_id();
+    let timeout = std::time::Duration::from_millis(10);
+    let x = bm.lock_exclusive(pid);
+    assert!(on_other_thread(|| bm.try_lock_optimistic(pid).is_none()));
+    assert!(on_other_thread(|| bm.try_lock_shared(pid).is_none()));
+    assert!(on_other_thread(|| bm.try_lock_exclusive(pid).is_none()));
+    assert!(on_other_thread(|| bm.lock_shared_timeout(pid, timeout).is_err()));
+    drop(x);
+    let s = bm.lock_shared(pid);
+    assert!(on_other_thread(|| bm.try_lock_optimistic(pid).is_some()));
+    assert!(on_other_thread(|| bm.try_lock_shared(pid).is_some()));
+    assert!(on_other_thread(|| bm.try_lock_exclusive(pid).is_none()));
+    assert!(on_other_thread(|| bm.lock_exclusive_timeout(pid, timeout).is_err()));
+    drop(s);
+    bm.try_lock_exclusive(pid).unwrap().data[0] = 1;
+    assert_eq!(bm.try_lock_shared(pid).unwrap().data[0], 1);
+}
+
+fn contended<L: PageLock>() {
+    let bm = SimpleBm::<Page, L>::new(4);
+    let bm = &bm;
+    let pids: Vec<PageId> = (0..4).map(|_| BufferManager::try_alloc(bm).unwrap().page_id()).collect();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let pids = &pids;
+            s.spawn(move || {
+                for i in 0..5000usize {
+                    let pid = pids[(i + t) % pids.len()];
+                    match i % 3 {
+                        0 => {
+                            let mut g = bm.lock_exclusive(pid);
+                            g.data[0] += 1;
+                            g.data[1] = g.data[0] * 2;
+                        }
+                        1 => {
+                            let g = bm.lock_shared(pid);
+                            assert_eq!(g.data[1], g.data[0] * 2);
+                        }
+                        _ => {
+                            let (x, y) = <&SimpleBm<Page, L>>::repeat(|| {
+                                let g = bm.lock_optimistic(pid);
+                                let data = g.o_ptr_bm().as_slice::<u64>();
+                                let xy = (data.i(0).r(), data.i(1).r());
+                                g.check();
+                                xy
+                            });
+                            assert_eq!(y, x * 2);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let total: u64 = pids.iter().map(|&pid| bm.lock_shared(pid).data[0]).sum();
+    assert_eq!(total, 4 * 1667);
+}
+
+#[test]
+fn spin_wait_contention() {
+    contended::<SeqLock<SpinWait>>();
+}
+
+#[test]
+fn yield_wait_contention() {
+    contended::<SeqLock<YieldWait>>();
+    contended::<SeqLock<Fair<YieldWait>>>();
+}
@@ -0,0 +1,139 @@
+use olc_utils::*;
+use std::collections::BTreeMap;
+
+/// Replays random inserts and removes on a page and a model, checking that the page never differs from the model.
+#[test]
+fn insert_remove_model() {
+    let mut buf = [0u8; 1024];
+    let mut p = SlottedPageX::init(&mut buf, b"ab");
+    let mut m: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+    let mut x = 7u64;
+    for i in 0..20000u64 {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let mut k = b"ab".to_vec();
+        k.extend_from_slice(format!("{}", (x >> 33) % 200).as_bytes());
+        let v = vec![i as u8; ((x >> 20) % 40) as usize];
+        if i % 3 == 0 {
+            assert_eq!(p.remove(&k), m.remove(&k).is_some());
+        } else if p.insert(&k, &v) {
+            m.insert(k.clone(), v);
+        } else {
+            let old = m.get(&k).map_or(0, |o| o.len() + k.len() - 2 + 6);
+            assert!(p.free_space_after_compaction() + old < k.len() - 2 + v.len() + 6);
+        }
+        assert_eq!(p.count(), m.len());
+        assert_eq!(p.lookup(&k), m.get(&k).map(|v| &v[..]));
+    }
+    let entries: Vec<_> = (0..p.count())
+        .map(|i| {
+            let mut k = p.prefix().to_vec();
+            k.extend_from_slice(p.key(i));
+            (k, p.value(i).to_vec())
+        })
+        .collect();
+    assert_eq!(entries, m.clone().into_iter().collect::<Vec<_>>());
+    let o = SlottedPageO::<UnwindOlcEh>::new(OPtr::from_mut(&mut buf).unsize());
+    for (i, (k, v)) in m.iter().enumerate() {
+        assert_eq!(o.load_key(i), *k);
+        assert_eq!(o.lookup(k).unwrap().load_slice_to_vec(), *v);
+    }
+    assert!(o.lookup(b"abzz").is_none());
+}
+
+#[test]
+fn replace_and_remove() {
+    let mut buf = [0u8; 256];
+    let mut p = SlottedPageX::init(&mut buf, b"");
+    assert!(p.insert(b"b", b"1"));
+    assert!(p.insert(b"a", b"2"));
+    assert!(p.insert(b"b", b"333"));
+    assert_eq!(p.count(), 2);
+    assert_eq!(p.lookup(b"b"), Some(&b"333"[..]));
+    assert!(!p.remove(b"c"));
+    assert!(p.remove(b"a"));
+    assert_eq!(p.lookup(b"a"), None);
+    assert_eq!(p.key(0), b"b");
+    p.remove_at(0);
+    assert_eq!(p.count(), 0);
+}
+
+#[test]
+fn insert_fails_when_full() {
+    let mut buf = [0u8; 128];
+    let mut p = SlottedPageX::init(&mut buf, b"");
+    let mut n = 0u8;
+    while p.insert(&[n], &[0; 10]) {
+        n += 1;
+    }
+    assert!(n > 0);
+    assert_eq!(p.count(), n as usize);
+    assert!(p.free_space_after_compaction() < 1 + 10 + 6);
+    for i in 0..n {
+        assert_eq!(p.lookup(&[i]), Some(&[0; 10][..]));
+    }
+}
+
+#[test]
+fn compact_reclaims_removed_records() {
+    let mut buf = [0u8; 256];
+    let mut p = SlottedPageX::init(&mut buf, b"");
+    for i in 0..8u8 {
+        assert!(p.insert(&[i], &[i; 20]));
+    }
+    for i in (0..8u8).step_by(2) {
+        assert!(p.remove(&[i]));
+    }
+    let free = p.free_space();
+    let after = p.free_space_after_compaction();
+    assert!(after > free);
+    p.compact();
+    assert_eq!(p.free_space(), after);
+    assert_eq!(p.free_space_after_compaction(), after);
+    for i in 0..8u8 {
+        assert_eq!(p.lookup(&[i]), (i % 2 == 1).then_some(&[i; 20][..]));
+    }
+    // reclaimed space is usable without another explicit compaction
+    for i in (0..8u8).step_by(2) {
+        assert!(p.insert(&[i], &[i; 20]));
+    }
+    assert_eq!(p.count(), 8);
+}
+
+#[test]
+fn lower_bound() {
+    let mut buf = [0u8; 256];
+    let mut p = SlottedPageX::init(&mut buf, b"pre");
+    for k in [&b"pre1"[..], b"pre3", b"pre5"] {
+        assert!(p.insert(k, b""));
+    }
+    assert_eq!(p.lower_bound(b"pre"), (0, false));
+    assert_eq!(p.lower_bound(b"pre1"), (0, true));
+    assert_eq!(p.lower_bound(b"pre2"), (1, false));
+    assert_eq!(p.lower_bound(b"pre5"), (2, true));
+    assert_eq!(p.lower_bound(b"pre6"), (3, false));
+    // keys that differ within the prefix sort before or after all records
+    assert_eq!(p.lower_bound(b"pa"), (0, false));
+    assert_eq!(p.lower_bound(b"pr"), (0, false));
+    assert_eq!(p.lower_bound(b"q"), (3, false));
+    let keys = [&b"pre"[..], b"pre1", b"pre2", b"pre5", b"pre6", b"pa", b"pr", b"q"];
+    let expected: Vec<_> = keys.iter().map(|k| p.lower_bound(k)).collect();
+    let o = SlottedPageO::<UnwindOlcEh>::new(OPtr::from_mut(&mut buf).unsize());
+    for (k, e) in keys.iter().zip(expected) {
+        assert_eq!(o.lower_bound(k), e);
+    }
+}
+
+#[test]
+fn corrupt_page_fails_optimistically() {
+    let mut buf = [0u8; 256];
+    SlottedPageX::init(&mut buf, b"").insert(b"k", b"v");
+    buf[0] = 200; // count
+    let o = SlottedPageO::<UnwindOlcEh>::new(OPtr::from_mut(&mut buf).unsize());
+    assert!(UnwindOlcEh::catch(|| o.lower_bound(b"zzz")).is_err());
+    let mut buf = [0u8; 256];
+    SlottedPageX::init(&mut buf, b"").insert(b"k", b"v");
+    buf[8] = 255;
+    buf[9] = 255; // record offset
+    let o = SlottedPageO::<UnwindOlcEh>::new(OPtr::from_mut(&mut buf).unsize());
+    assert!(UnwindOlcEh::catch(|| o.key(0).load_slice_to_vec()).is_err());
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::temp_path;
+use olc_utils::*;
+
+/// the page size must be a multiple of the OS page size
+#[derive(Clone, Copy, bytemuck::Zeroable, bytemuck::Pod)]
+#[repr(C)]
+struct Page {
+    data: [u64; 512],
+}
+
+fn alloc_pages(bm: &VmCacheBm<Page>, n: u64) -> Vec<PageId> {
+    (0..n)
+        .map(|i| {
+            let mut g = BufferManager::alloc(bm);
+            g.data[0] = i;
+            g.data[511] = i * 2;
+            g.page_id()
+        })
+        .collect()
+}
+
+#[test]
+fn evict_and_reopen() {
+    let path = temp_path("vmcache_evict");
+    let pids: Vec<PageId>;
+    {
+        let bm = VmCacheBm::<Page>::open(&path, 4, 1000).unwrap();
+        let bm = &bm;
+        pids = alloc_pages(bm, 100);
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let pids = &pids;
+                s.spawn(move || {
+                    for r in 0..2000usize {
+                        let pid = pids[(r * 7 + t * 13) % 100];
+                        match r % 3 {
+                            0 => bm.lock_exclusive(pid).data[1] += 1,
+                            1 => {
+                                let g = bm.lock_shared(pid);
+                                assert_eq!(g.data[511], g.data[0] * 2);
+                            }
+                            _ => {
+                                // the page may be evicted while it is read, which zeroes it
+                                let (x, y) = <&VmCacheBm<Page>>::repeat(|| {
+                                    let g = bm.lock_optimistic(pid);
+                                    let data = g.o_ptr_bm().as_slice::<u64>();
+                                    let xy = (data.i(0).r(), data.i(511).r());
+                                    g.check();
+                                    xy
+                                });
+                                assert_eq!(y, x * 2);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+    }
+    let bm = VmCacheBm::<Page>::open(&path, 4, 1000).unwrap();
+    let mut total = 0;
+    for (i, &pid) in pids.iter().enumerate() {
+        let g = (&bm).lock_shared(pid);
+        assert_eq!(g.data[0], i as u64);
+        total += g.data[1];
+    }
+    assert_eq!(total, 4 * 667);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn flush_and_reopen() {
+    let path = temp_path("vmcache_flush");
+    let bm = VmCacheBm::<Page>::open(&path, 8, 100).unwrap();
+    let pids = alloc_pages(&bm, 4);
+    assert_eq!(bm.dirty_pages().len(), 4);
+    bm.flush().unwrap();
+    assert!(bm.dirty_pages().is_empty());
+    // exclusive guards that only read leave the page clean
+    assert_eq!((&bm).lock_exclusive(pids[0]).data[0], 0);
+    (&bm).lock_exclusive(pids[1]).data[1] = 7;
+    assert_eq!(bm.dirty_pages(), vec![pids[1]]);
+    assert!(bm.flush_page(pids[1]).unwrap());
+    assert!(!bm.flush_page(pids[1]).unwrap());
+    assert!(bm.dirty_pages().is_empty());
+    bm.flush().unwrap();
+    // the flushed file is complete without dropping the buffer manager
+    let reopened = VmCacheBm::<Page>::open(&path, 8, 100).unwrap();
+    for (i, &pid) in pids.iter().enumerate() {
+        let g = (&reopened).lock_shared(pid);
+        assert_eq!(g.data[0], i as u64);
+        assert_eq!(g.data[1], if i == 1 { 7 } else { 0 });
+    }
+    drop(reopened);
+    drop(bm);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn dealloc_and_reuse() {
+    let path = temp_path("vmcache_reuse");
+    {
+        let bm = VmCacheBm::<Page>::open(&path, 2, 100).unwrap();
+        let bm = &bm;
+        let pids = alloc_pages(bm, 8);
+        bm.flush().unwrap();
+        bm.lock_exclusive(pids[3]).dealloc();
+        bm.lock_exclusive(pids[6]).dealloc();
+        let g = BufferManager::alloc(bm);
+        assert_eq!(g.page_id(), pids[6]);
+        assert_eq!(g.data, [0; 512]);
+        drop(g);
+        let g = BufferManager::alloc(bm);
+        assert_eq!(g.page_id(), pids[3]);
+        assert_eq!(g.data, [0; 512]);
+    }
+    // the reused pages were written back zeroed, the others are unchanged
+    let bm = VmCacheBm::<Page>::open(&path, 2, 100).unwrap();
+    for x in 0..8 {
+        let g = (&bm).lock_shared(PageId { x });
+        assert_eq!(g.data[0], if x == 3 || x == 6 { 0 } else { x });
+    }
+    drop(bm);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn load_with_pinned_pool() {
+    let path = temp_path("vmcache_pinned");
+    let bm = VmCacheBm::<Page>::open(&path, 2, 100).unwrap();
+    let bm = &bm;
+    let pids = alloc_pages(bm, 3);
+    let (evicted, resident): (Vec<PageId>, Vec<PageId>) =
+        pids.iter().partition(|&&pid| !CommonSeqLockBM::is_resident(bm, pid));
+    let (evicted, b) = (evicted[0], bm.lock_exclusive(resident[0]));
+    let c = bm.lock_exclusive(resident[1]);
+    let timeout = std::time::Duration::from_millis(10);
+    std::thread::scope(|s| {
+        assert!(s.spawn(|| bm.try_lock_shared(evicted).is_none()).join().unwrap());
+        assert!(s.spawn(|| bm.lock_shared_timeout(evicted, timeout).is_err()).join().unwrap());
+        // a blocking acquire waits until a resident page is unlocked
+        let waiting = s.spawn(|| bm.lock_shared(evicted).data[0]);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(b);
+        assert_eq!(waiting.join().unwrap(), evicted.x);
+    });
+    drop(c);
+    std::fs::remove_file(&path).unwrap();
+}